@@ -0,0 +1,215 @@
+use std::{io, path::Path};
+
+use crate::formats::to_tag;
+
+/// `XATTR_NAME` is the extended attribute tags are stored in, following
+/// the freedesktop convention.
+pub const XATTR_NAME: &str = "user.xdg.tags";
+
+/// `parse_xattr_tags()` returns the tags of a comma separated list of
+/// tag names as stored in `XATTR_NAME`. Invalid tags are ignored.
+fn parse_xattr_tags(value: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    for tag in value.split(',').filter_map(|name| to_tag(name.trim())) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    tags
+}
+
+/// `format_xattr_tags()` returns a list of tags as a comma separated list
+/// of tag names as stored in `XATTR_NAME`.
+fn format_xattr_tags(tags: &[String]) -> String {
+    tags.iter()
+        .map(|tag| tag.trim_start_matches('#'))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// `read_xattr_tags()` returns the tags stored in the extended attributes
+/// of a file. A file without the attribute has no tags.
+///
+/// # Errors
+///
+/// This function errors if the extended attributes of the file can't be read
+/// or extended attributes aren't supported on the current platform.
+#[cfg(unix)]
+pub fn read_xattr_tags(path: &Path) -> io::Result<Vec<String>> {
+    let Some(value) = xattr::get(path, XATTR_NAME)? else {
+        return Ok(Vec::new());
+    };
+
+    Ok(parse_xattr_tags(&String::from_utf8_lossy(&value)))
+}
+
+/// `read_xattr_tags()` returns the tags stored in the extended attributes
+/// of a file. A file without the attribute has no tags.
+///
+/// # Errors
+///
+/// This function errors if the extended attributes of the file can't be read
+/// or extended attributes aren't supported on the current platform.
+#[cfg(not(unix))]
+pub fn read_xattr_tags(_path: &Path) -> io::Result<Vec<String>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// `write_xattr_tags()` stores tags in the extended attributes of a file.
+/// An empty list of tags removes the attribute.
+///
+/// # Errors
+///
+/// This function errors if the extended attributes of the file can't be written
+/// or extended attributes aren't supported on the current platform.
+#[cfg(unix)]
+pub fn write_xattr_tags(path: &Path, tags: &[String]) -> io::Result<()> {
+    if tags.is_empty() {
+        return match xattr::remove(path, XATTR_NAME) {
+            Err(e) if xattr::get(path, XATTR_NAME)?.is_some() => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    xattr::set(path, XATTR_NAME, format_xattr_tags(tags).as_bytes())
+}
+
+/// `write_xattr_tags()` stores tags in the extended attributes of a file.
+/// An empty list of tags removes the attribute.
+///
+/// # Errors
+///
+/// This function errors if the extended attributes of the file can't be written
+/// or extended attributes aren't supported on the current platform.
+#[cfg(not(unix))]
+pub fn write_xattr_tags(_path: &Path, _tags: &[String]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// `add_xattr_tags()` adds tags to the extended attributes of a file.
+/// The tag names already stored are kept, even if they aren't valid tags.
+///
+/// # Errors
+///
+/// This function errors if the extended attributes of the file can't be read
+/// or written or extended attributes aren't supported on the current platform.
+#[cfg(unix)]
+pub fn add_xattr_tags(path: &Path, tags: &[String]) -> io::Result<()> {
+    let value = xattr::get(path, XATTR_NAME)?.unwrap_or_default();
+    let value = String::from_utf8_lossy(&value);
+
+    let mut names = value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+    let stored = names.len();
+    for name in tags.iter().map(|tag| tag.trim_start_matches('#')) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    if names.len() == stored {
+        return Ok(());
+    }
+
+    xattr::set(path, XATTR_NAME, names.join(",").as_bytes())
+}
+
+/// `add_xattr_tags()` adds tags to the extended attributes of a file.
+/// The tag names already stored are kept, even if they aren't valid tags.
+///
+/// # Errors
+///
+/// This function errors if the extended attributes of the file can't be read
+/// or written or extended attributes aren't supported on the current platform.
+#[cfg(not(unix))]
+pub fn add_xattr_tags(_path: &Path, _tags: &[String]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_xattr_tags, format_xattr_tags, parse_xattr_tags, read_xattr_tags, write_xattr_tags,
+        XATTR_NAME,
+    };
+
+    #[test]
+    fn test_parse_xattr_tags() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_tags: Vec<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success",
+                input: "work,project-x, notes",
+                expected_tags: vec!["#work", "#project-x", "#notes"],
+            },
+            TestCase {
+                name: "success_skip_invalid",
+                input: "work,,with space,work",
+                expected_tags: vec!["#work"],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_parse_xattr_tags: \n\t{}", test_case.name);
+
+            assert_eq!(test_case.expected_tags, parse_xattr_tags(test_case.input));
+        }
+    }
+
+    #[test]
+    fn test_format_xattr_tags() {
+        let tags = vec!["#work".to_string(), "#project-x".to_string()];
+
+        assert_eq!("work,project-x", format_xattr_tags(&tags));
+    }
+
+    #[test]
+    fn test_xattr_roundtrip() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path();
+
+        let tags = vec!["#work".to_string(), "#project-x".to_string()];
+        let res = write_xattr_tags(path, &tags);
+
+        // the scratch directory might not support extended attributes
+        if let Err(e) = res {
+            println!("test_xattr_roundtrip: skipped: {e}");
+            return;
+        }
+
+        assert_eq!(tags, read_xattr_tags(path).unwrap());
+
+        write_xattr_tags(path, &[]).unwrap();
+        assert!(read_xattr_tags(path).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_add_xattr_tags() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path();
+
+        // the scratch directory might not support extended attributes
+        if let Err(e) = xattr::set(path, XATTR_NAME, b"old, not a tag,keep") {
+            println!("test_add_xattr_tags: skipped: {e}");
+            return;
+        }
+
+        add_xattr_tags(path, &["#keep".to_string(), "#new".to_string()]).unwrap();
+
+        assert_eq!(
+            b"old,not a tag,keep,new".to_vec(),
+            xattr::get(path, XATTR_NAME).unwrap().unwrap()
+        );
+    }
+}
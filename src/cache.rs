@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::search::SearchOptions;
+
+/// `CACHE_VERSION` is increased whenever the format of cache files or the
+/// way tags are read changes, which invalidates all existing caches.
+const CACHE_VERSION: u32 = 2;
+
+/// `default_cache_dir()` returns the directory caches are stored in by
+/// default, which is `$XDG_CACHE_HOME/tag` or `~/.cache/tag`.
+#[must_use]
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            if cfg!(windows) {
+                env::var_os("LOCALAPPDATA").map(PathBuf::from)
+            } else {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache"))
+            }
+        })?;
+
+    Some(cache_home.join("tag"))
+}
+
+/// `FileStamp` contains the metadata of a file used to detect changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileStamp {
+    size: u64,
+    /// The modification time in seconds and nanoseconds since the epoch.
+    modified: Option<(u64, u32)>,
+    /// The status change time in seconds and nanoseconds since the epoch,
+    /// which also changes when extended attributes are written.
+    changed: Option<(i64, i64)>,
+}
+
+impl FileStamp {
+    /// `new()` reads the stamp of a file.
+    pub(crate) fn new(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| (modified.as_secs(), modified.subsec_nanos()));
+
+        #[cfg(unix)]
+        let changed = {
+            use std::os::unix::fs::MetadataExt;
+            Some((metadata.ctime(), metadata.ctime_nsec()))
+        };
+        #[cfg(not(unix))]
+        let changed = None;
+
+        Ok(Self {
+            size: metadata.len(),
+            modified,
+            changed,
+        })
+    }
+}
+
+/// `CacheEntry` contains the tags read from a file when it had the given stamp.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CacheEntry {
+    pub(crate) stamp: FileStamp,
+    pub(crate) tags: Option<Vec<String>>,
+}
+
+/// `CacheFile` is the content of a cache file.
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    root: PathBuf,
+    /// The entries keyed by the paths of the files relative to the root.
+    files: HashMap<String, CacheEntry>,
+}
+
+/// `IndexCache` stores the tags of the files of a directory, so that only
+/// files whose metadata changed since the last search need to be read.
+pub(crate) struct IndexCache {
+    file: PathBuf,
+    /// The canonical path of the directory.
+    root: PathBuf,
+    /// The path of the directory as it was given, which is the prefix of the
+    /// paths of the files found while walking it.
+    directory: PathBuf,
+    /// The entries loaded from the cache file.
+    previous: HashMap<String, CacheEntry>,
+    /// The entries of files found during the current search. Files that
+    /// couldn't be cached have no entry.
+    current: HashMap<String, Option<CacheEntry>>,
+}
+
+/// `hash()` computes the FNV-1a hash of some bytes. Unlike the hasher of
+/// the standard library it is guaranteed to be stable.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl IndexCache {
+    /// `load()` loads the cache of a directory from the cache directory.
+    /// Every combination of options that changes how tags are read has its
+    /// own cache. A missing, outdated or corrupted cache is treated as empty.
+    pub(crate) fn load(cache_dir: &Path, directory: &str, options: &SearchOptions) -> Self {
+        let root = fs::canonicalize(directory).unwrap_or_else(|_| PathBuf::from(directory));
+        let key = format!(
+            "{}\0{}\0{}",
+            root.to_string_lossy(),
+            options.inline_tags,
+            options.xattr_tags
+        );
+        let file = cache_dir.join(format!("{:016x}.json", hash(key.as_bytes())));
+
+        let previous = if options.rebuild_cache {
+            HashMap::new()
+        } else {
+            fs::read(&file)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<CacheFile>(&content).ok())
+                .filter(|cache| cache.version == CACHE_VERSION && cache.root == root)
+                .map(|cache| cache.files)
+                .unwrap_or_default()
+        };
+
+        Self {
+            file,
+            root,
+            directory: PathBuf::from(directory),
+            previous,
+            current: HashMap::new(),
+        }
+    }
+
+    /// `key()` returns the key of a file found while walking the directory,
+    /// which is its path relative to the directory. This way the cache is
+    /// shared by all paths the directory is searched by.
+    fn key<'a>(&self, path: &'a Path) -> Option<&'a str> {
+        path.strip_prefix(&self.directory).ok()?.to_str()
+    }
+
+    /// `get()` returns the cached tags of a file if it didn't change.
+    pub(crate) fn get(&self, path: &Path, stamp: &FileStamp) -> Option<&Option<Vec<String>>> {
+        self.previous
+            .get(self.key(path)?)
+            .filter(|entry| entry.stamp == *stamp)
+            .map(|entry| &entry.tags)
+    }
+
+    /// `insert()` records the result of reading a file during the current
+    /// search. Files without an entry won't be cached.
+    pub(crate) fn insert(&mut self, path: &Path, entry: Option<CacheEntry>) {
+        if let Some(key) = self.key(path) {
+            self.current.insert(key.to_string(), entry);
+        }
+    }
+
+    /// `save()` writes the cache to the cache directory. Entries of files
+    /// that weren't found during the current search, e.g. because of
+    /// filters, are kept as long as the files still exist.
+    pub(crate) fn save(self) -> io::Result<()> {
+        let mut files: HashMap<String, CacheEntry> = self
+            .current
+            .iter()
+            .filter_map(|(path, entry)| Some((path.clone(), entry.clone()?)))
+            .collect();
+
+        for (path, entry) in self.previous {
+            if !self.current.contains_key(&path) && self.root.join(&path).is_file() {
+                files.insert(path, entry);
+            }
+        }
+
+        let content = serde_json::to_vec(&CacheFile {
+            version: CACHE_VERSION,
+            root: self.root,
+            files,
+        })?;
+
+        if let Some(cache_dir) = self.file.parent() {
+            fs::create_dir_all(cache_dir)?;
+        }
+
+        // write to a temporary file first, so that concurrent searches never
+        // read a partially written cache
+        let temporary = self
+            .file
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, &self.file)
+    }
+
+    /// `file()` returns the path of the cache file.
+    pub(crate) fn file(&self) -> &Path {
+        &self.file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{hash, CacheEntry, FileStamp, IndexCache};
+    use crate::search::SearchOptions;
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, hash(b""));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, hash(b"a"));
+    }
+
+    /// `write_test_cache()` creates four files in `root` and caches all of
+    /// them except `c.md`.
+    fn write_test_cache(root: &Path, cache_dir: &Path) {
+        std::fs::write(root.join("a.md"), "tags: [#a]\n").unwrap();
+        std::fs::write(root.join("b.md"), "no tags\n").unwrap();
+        std::fs::write(root.join("c.md"), "tags: [#c]\n").unwrap();
+        std::fs::write(root.join("d.md"), "tags: [#d]\n").unwrap();
+
+        let mut cache =
+            IndexCache::load(cache_dir, root.to_str().unwrap(), &SearchOptions::default());
+        for (file, tags) in [
+            ("a.md", Some(vec!["#a".to_string()])),
+            ("b.md", None),
+            ("d.md", Some(vec!["#d".to_string()])),
+        ] {
+            let path = root.join(file);
+            let stamp = FileStamp::new(&path).unwrap();
+            cache.insert(&path, Some(CacheEntry { stamp, tags }));
+        }
+        cache.insert(&root.join("c.md"), None);
+        cache.save().unwrap();
+    }
+
+    #[test]
+    fn test_index_cache() {
+        struct TestCase<'a> {
+            name: &'a str,
+            options: SearchOptions,
+            /// The path the directory is loaded by, relative to the root.
+            directory: &'a str,
+            file: &'a str,
+            expected_cached: bool,
+            expected_tags: Vec<&'a str>,
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let cache_dir = root.join("cache");
+        write_test_cache(root, &cache_dir);
+
+        // changes the size of the file, so its stamp changes
+        std::fs::write(root.join("d.md"), "tags: [#d #e]\n").unwrap();
+
+        let test_cases = [
+            TestCase {
+                name: "success_unchanged",
+                options: SearchOptions::default(),
+                directory: "",
+                file: "a.md",
+                expected_cached: true,
+                expected_tags: vec!["#a"],
+            },
+            TestCase {
+                name: "success_unchanged_without_tags",
+                options: SearchOptions::default(),
+                directory: "",
+                file: "b.md",
+                expected_cached: true,
+                expected_tags: vec![],
+            },
+            TestCase {
+                name: "success_other_path",
+                options: SearchOptions::default(),
+                directory: "cache/..",
+                file: "a.md",
+                expected_cached: true,
+                expected_tags: vec!["#a"],
+            },
+            TestCase {
+                name: "success_not_cached",
+                options: SearchOptions::default(),
+                directory: "",
+                file: "c.md",
+                expected_cached: false,
+                expected_tags: vec![],
+            },
+            TestCase {
+                name: "success_changed",
+                options: SearchOptions::default(),
+                directory: "",
+                file: "d.md",
+                expected_cached: false,
+                expected_tags: vec![],
+            },
+            TestCase {
+                name: "success_other_options",
+                options: SearchOptions {
+                    inline_tags: true,
+                    ..Default::default()
+                },
+                directory: "",
+                file: "a.md",
+                expected_cached: false,
+                expected_tags: vec![],
+            },
+            TestCase {
+                name: "success_rebuild",
+                options: SearchOptions {
+                    rebuild_cache: true,
+                    ..Default::default()
+                },
+                directory: "",
+                file: "a.md",
+                expected_cached: false,
+                expected_tags: vec![],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_index_cache: \n\t{}", test_case.name);
+
+            let directory = root.join(test_case.directory);
+            let cache =
+                IndexCache::load(&cache_dir, directory.to_str().unwrap(), &test_case.options);
+            let path = directory.join(test_case.file);
+            let tags = cache.get(&path, &FileStamp::new(&path).unwrap());
+
+            assert_eq!(test_case.expected_cached, tags.is_some());
+            assert_eq!(
+                test_case.expected_tags,
+                tags.cloned().flatten().unwrap_or_default()
+            );
+        }
+    }
+}
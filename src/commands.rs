@@ -1,69 +1,1669 @@
-use std::{path::Path, process::Command};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    io::{self, Read},
+    path::Path,
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::OnceLock,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-use colored::Colorize;
+#[cfg(unix)]
+use std::sync::{
+    atomic::{AtomicI32, Ordering},
+    Once,
+};
 
-/// `execute_command_on_file` executes a command on a given #FILE#.
-///
-/// # Panics
+use crate::error::Error;
+use crate::search::TaggedFile;
+
+/// `FILE_PLACEHOLDER` is replaced with the shell-escaped path of a file
+/// in shell commands.
+pub const FILE_PLACEHOLDER: &str = "#FILE#";
+
+/// `EXEC_PLACEHOLDER` is replaced with the path of a file in the arguments
+/// of programs that are executed without a shell.
+pub const EXEC_PLACEHOLDER: &str = "{}";
+
+/// `FILES_PLACEHOLDER` is replaced with the shell-escaped paths of all
+/// matched files in batch commands.
+pub const FILES_PLACEHOLDER: &str = "#FILES#";
+
+/// `BATCH_COMMAND_LIMIT` is the maximum length of a batch command in bytes.
+/// The whole command is passed to the shell as a single argument, which
+/// Linux limits to 128 KiB and `cmd` to 8191 characters.
+const BATCH_COMMAND_LIMIT: usize = if cfg!(target_os = "windows") {
+    8191
+} else {
+    128 * 1024 - 1
+};
+
+/// `POLL_INTERVAL` is how often a command with a timeout is checked for
+/// having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// `CommandOptions` contains the options used to execute filter commands and
+/// commands on files.
+#[derive(Debug, Clone, Default)]
+pub struct CommandOptions {
+    /// Commands running longer than this are killed together with all
+    /// processes they started.
+    pub timeout: Option<Duration>,
+    /// The shell executing commands. If it isn't set, `default_shell()` is
+    /// used.
+    pub shell: Option<Shell>,
+}
+
+/// `Shell` is a program executing commands whose quoting is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shell {
+    program: OsString,
+    kind: ShellKind,
+}
+
+impl Shell {
+    /// `new()` creates a shell from its program, e.g. `bash` or
+    /// `/usr/bin/fish`. Returns `None` if the shell isn't a POSIX shell,
+    /// fish, PowerShell or `cmd`, since placeholders couldn't be quoted for
+    /// it.
+    #[must_use]
+    pub fn new(program: impl Into<OsString>) -> Option<Self> {
+        let program = program.into();
+        ShellKind::from_program(&program).map(|kind| Self { program, kind })
+    }
+}
+
+/// `default_shell()` returns the shell used if no shell is configured. This
+/// is `cmd` on Windows and otherwise the shell in `$SHELL` if it is known,
+/// `bash` or, if bash isn't installed, `sh`.
 ///
-/// This function panics if it fails to convert the given path to a `&str`.
+/// The shell is determined once, on the first call.
 #[must_use]
-pub fn execute_command_on_file(path: &Path, command: &str) -> String {
-    let command = command.replace("#FILE#", path.to_str().unwrap());
+pub fn default_shell() -> &'static Shell {
+    static DEFAULT: OnceLock<Shell> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        if cfg!(target_os = "windows") {
+            return Shell {
+                program: "cmd".into(),
+                kind: ShellKind::Cmd,
+            };
+        }
+
+        choose_shell(env::var_os("SHELL"), || is_installed("bash"))
+    })
+}
+
+/// `choose_shell` chooses the default shell on Unix from the value of
+/// `$SHELL` and whether bash is installed.
+fn choose_shell(environment: Option<OsString>, has_bash: impl FnOnce() -> bool) -> Shell {
+    environment.and_then(Shell::new).unwrap_or_else(|| Shell {
+        program: if has_bash() { "bash" } else { "sh" }.into(),
+        kind: ShellKind::Posix,
+    })
+}
+
+/// `is_installed` checks if a program can be found in `$PATH`.
+fn is_installed(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|directory| directory.join(program).is_file())
+    })
+}
+
+/// `Quote` is the kind of quotes a part of a shell command is inside of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+/// `ShellKind` is the kind of shell executing commands, which determines how it
+/// is invoked and how values are quoted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShellKind {
+    /// `sh` and shells with compatible quoting like bash or zsh.
+    Posix,
+    Fish,
+    Cmd,
+    PowerShell,
+}
+
+impl ShellKind {
+    /// `from_program()` determines the kind of a shell from its program.
+    /// Returns `None` for unknown shells.
+    fn from_program(program: &OsStr) -> Option<Self> {
+        let name = Path::new(program)
+            .file_stem()?
+            .to_string_lossy()
+            .to_ascii_lowercase();
+
+        match name.as_str() {
+            "sh" | "bash" | "zsh" | "dash" | "ksh" | "mksh" | "ash" | "yash" => Some(Self::Posix),
+            "fish" => Some(Self::Fish),
+            "cmd" => Some(Self::Cmd),
+            "powershell" | "pwsh" => Some(Self::PowerShell),
+            _ => None,
+        }
+    }
+
+    /// `flag()` returns the flag that makes the shell execute the command
+    /// given as the next argument.
+    const fn flag(self) -> &'static str {
+        match self {
+            Self::Posix | Self::Fish => "-c",
+            Self::Cmd => "/C",
+            Self::PowerShell => "-Command",
+        }
+    }
+
+    /// `is_quote()` checks if a character opens or closes quotes of a kind.
+    /// PowerShell also treats typographic quotes as quotes.
+    const fn is_quote(self, c: char, quote: Quote) -> bool {
+        match (self, quote) {
+            (Self::PowerShell, Quote::Single) => matches!(c, '\'' | '\u{2018}'..='\u{201b}'),
+            (Self::PowerShell, Quote::Double) => matches!(c, '"' | '\u{201c}'..='\u{201e}'),
+            (Self::Cmd, Quote::Single) | (_, Quote::None) => false,
+            (_, Quote::Single) => c == '\'',
+            (_, Quote::Double) => c == '"',
+        }
+    }
+
+    /// `escape_character()` returns the character escaping the next one
+    /// inside the given quotes, if there is one.
+    const fn escape_character(self, quote: Quote) -> Option<char> {
+        match (self, quote) {
+            (Self::Posix | Self::PowerShell, Quote::Single) | (Self::Cmd, Quote::Double) => None,
+            (Self::Cmd, _) => Some('^'),
+            (Self::PowerShell, _) => Some('`'),
+            (Self::Posix | Self::Fish, _) => Some('\\'),
+        }
+    }
+
+    /// `scan_quotes()` returns the quotes that are open after `text` if
+    /// `quote` is open before it. Returns `None` if the text ends with an
+    /// escape character, which would apply to whatever follows it.
+    fn scan_quotes(self, text: &str, mut quote: Quote) -> Option<Quote> {
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if Some(c) == self.escape_character(quote) {
+                chars.next()?;
+                continue;
+            }
+
+            if quote == Quote::None {
+                if self.is_quote(c, Quote::Single) {
+                    quote = Quote::Single;
+                } else if self.is_quote(c, Quote::Double) {
+                    quote = Quote::Double;
+                }
+            } else if self.is_quote(c, quote) {
+                // PowerShell escapes quotes inside quotes by doubling them
+                if self == Self::PowerShell
+                    && chars.peek().is_some_and(|&c| self.is_quote(c, quote))
+                {
+                    chars.next();
+                } else {
+                    quote = Quote::None;
+                }
+            }
+        }
+
+        Some(quote)
+    }
+
+    /// `escape_in()` escapes a string that is inserted inside the given
+    /// quotes of a command, so that it can't end them. Outside of quotes,
+    /// the string is quoted with `escape()`.
+    fn escape_in(self, value: &str, quote: Quote) -> Option<String> {
+        let escape_chars = |special: &[char], escape: char| {
+            let mut escaped = String::new();
+            for c in value.chars() {
+                if special.contains(&c) {
+                    escaped.push(escape);
+                }
+                escaped.push(c);
+            }
+            escaped
+        };
+
+        match (self, quote) {
+            (Self::Cmd, Quote::Single) | (_, Quote::None) => self.escape(value),
+            (Self::Posix, Quote::Single) => Some(value.replace('\'', "'\\''")),
+            (Self::Posix, Quote::Double) => Some(escape_chars(&['\\', '"', '$', '`'], '\\')),
+            (Self::Fish, Quote::Single) => Some(escape_chars(&['\\', '\''], '\\')),
+            (Self::Fish, Quote::Double) => Some(escape_chars(&['\\', '"', '$'], '\\')),
+            (Self::PowerShell, Quote::Double) => Some(escape_chars(
+                &['`', '$', '"', '\u{201c}', '\u{201d}', '\u{201e}'],
+                '`',
+            )),
+            // the quotes are the same, so only the surrounding ones are removed
+            (Self::PowerShell, Quote::Single) | (Self::Cmd, Quote::Double) => self
+                .escape(value)
+                .map(|quoted| quoted[1..quoted.len() - 1].to_string()),
+        }
+    }
+
+    /// `escape()` quotes a string, so that the shell passes it to a command
+    /// as a single argument without interpreting it. Returns `None` if the
+    /// string can't be quoted safely, which is the case for `cmd` if it
+    /// contains `%` or `"`, since `cmd` expands variables even inside quotes.
+    fn escape(self, value: &str) -> Option<String> {
+        match self {
+            Self::Posix => Some(format!("'{}'", value.replace('\'', "'\\''"))),
+            // fish also interprets backslashes inside single quotes
+            Self::Fish => Some(format!(
+                "'{}'",
+                value.replace('\\', "\\\\").replace('\'', "\\'")
+            )),
+            Self::Cmd => {
+                if value.contains(['%', '"', '\n', '\r']) {
+                    return None;
+                }
+                // trailing backslashes would escape the closing quote for
+                // the program parsing its arguments
+                let backslashes = value.len() - value.trim_end_matches('\\').len();
+                Some(format!("\"{value}{}\"", "\\".repeat(backslashes)))
+            }
+            // PowerShell also treats typographic quotes as single quotes
+            Self::PowerShell => {
+                let mut quoted = String::from("'");
+                for c in value.chars() {
+                    if matches!(c, '\'' | '\u{2018}'..='\u{201b}') {
+                        quoted.push(c);
+                    }
+                    quoted.push(c);
+                }
+                quoted.push('\'');
+                Some(quoted)
+            }
+        }
+    }
+}
+
+/// `CommandResult` is the result of a command that was executed on a file.
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    /// The standard output of the command. Output that isn't valid UTF-8 is
+    /// decoded lossily.
+    pub stdout: String,
+    /// The standard error of the command, decoded like `stdout`.
+    pub stderr: String,
+    pub status: ExitStatus,
+    /// How long the command ran.
+    pub duration: Duration,
+}
+
+impl CommandResult {
+    /// `new()` creates the result of a command from its output.
+    fn new(output: &Output, duration: Duration) -> Self {
+        Self {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status,
+            duration,
+        }
+    }
+
+    /// `success()` checks if the command exited successfully.
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// `summary()` describes how the command exited and how long it ran,
+    /// e.g. "exited with code 1 after 12.30ms".
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let status = self
+            .status
+            .code()
+            .map_or_else(|| self.status.to_string(), |code| format!("code {code}"));
+
+        format!("exited with {status} after {:.2?}", self.duration)
+    }
+}
+
+/// `Placeholder` is a value of a file that can be used in commands, either
+/// as a placeholder like `#FILE#` or as an environment variable like
+/// `TAG_FILE`.
+struct Placeholder {
+    name: &'static str,
+    env: &'static str,
+    value: OsString,
+}
+
+/// `placeholders` returns the placeholders of a file:
+///
+/// - `#FILE#`: the path of the file
+/// - `#DIR#`: the directory of the file
+/// - `#NAME#`: the file name
+/// - `#STEM#`: the file name without its extension
+/// - `#EXT#`: the extension of the file
+/// - `#RELPATH#`: the path of the file relative to the searched directory
+/// - `#ROOT#`: the searched directory
+/// - `#TAGS#`: the tags of the file, separated by spaces
+fn placeholders(file: &TaggedFile) -> Vec<Placeholder> {
+    let path = &file.path;
+    let os_string = |value: Option<&OsStr>| value.map(OsStr::to_owned).unwrap_or_default();
+
+    vec![
+        Placeholder {
+            name: FILE_PLACEHOLDER,
+            env: "TAG_FILE",
+            value: path.clone().into_os_string(),
+        },
+        Placeholder {
+            name: "#DIR#",
+            env: "TAG_DIR",
+            value: os_string(path.parent().map(Path::as_os_str)),
+        },
+        Placeholder {
+            name: "#NAME#",
+            env: "TAG_NAME",
+            value: os_string(path.file_name()),
+        },
+        Placeholder {
+            name: "#STEM#",
+            env: "TAG_STEM",
+            value: os_string(path.file_stem()),
+        },
+        Placeholder {
+            name: "#EXT#",
+            env: "TAG_EXT",
+            value: os_string(path.extension()),
+        },
+        Placeholder {
+            name: "#RELPATH#",
+            env: "TAG_RELPATH",
+            value: path
+                .strip_prefix(&file.root)
+                .unwrap_or(path)
+                .as_os_str()
+                .to_owned(),
+        },
+        Placeholder {
+            name: "#ROOT#",
+            env: "TAG_ROOT",
+            value: file.root.clone().into_os_string(),
+        },
+        Placeholder {
+            name: "#TAGS#",
+            env: "TAG_TAGS",
+            value: file.tags.join(" ").into(),
+        },
+    ]
+}
+
+/// `Part` is a part of a command with expanded placeholders.
+enum Part<'a> {
+    Text(&'a str),
+    Value(OsString),
+}
+
+/// `parse_tag_placeholder` parses a `#TAG[n]#` placeholder at the start of
+/// a string and returns its length and `n`.
+fn parse_tag_placeholder(text: &str) -> Option<(usize, usize)> {
+    let rest = text.strip_prefix("#TAG[")?;
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 || !rest[digits..].starts_with("]#") {
+        return None;
+    }
+
+    Some((
+        "#TAG[".len() + digits + "]#".len(),
+        rest[..digits].parse().ok()?,
+    ))
+}
+
+/// `expand_placeholders` splits a command into text and the values of the
+/// placeholders it contains. `#TAG[n]#` is replaced with the `n`th tag of
+/// the file, counted from zero, or nothing if the file has less tags.
+fn expand_placeholders<'a>(
+    command: &'a str,
+    placeholders: &[Placeholder],
+    tags: &[String],
+) -> Vec<Part<'a>> {
+    let mut parts = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+
+    while let Some(rest) = command.get(i..).filter(|rest| !rest.is_empty()) {
+        let placeholder = placeholders
+            .iter()
+            .find(|placeholder| rest.starts_with(placeholder.name))
+            .map(|placeholder| (placeholder.name.len(), placeholder.value.clone()))
+            .or_else(|| {
+                parse_tag_placeholder(rest)
+                    .map(|(length, n)| (length, tags.get(n).cloned().unwrap_or_default().into()))
+            });
+
+        match placeholder {
+            Some((length, value)) => {
+                parts.push(Part::Text(&command[text_start..i]));
+                parts.push(Part::Value(value));
+                i += length;
+                text_start = i;
+            }
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    parts.push(Part::Text(&command[text_start..]));
+    parts
+}
+
+/// `command_error` returns the error of a command that can't be built.
+fn command_error(command: &str, message: String) -> Error {
+    Error::Command {
+        command: command.to_string(),
+        source: io::Error::new(io::ErrorKind::InvalidData, message),
+    }
+}
+
+/// `escaped_placeholder_error` returns the error of a placeholder that
+/// follows an escape character, which would only escape its first character.
+fn escaped_placeholder_error(command: &str) -> Error {
+    command_error(
+        command,
+        "a placeholder can't follow an escape character".to_string(),
+    )
+}
+
+/// `escape_value` shell-escapes a value used in a command inside the given
+/// quotes.
+fn escape_value(
+    command: &str,
+    value: &OsStr,
+    shell: ShellKind,
+    quote: Quote,
+) -> Result<String, Error> {
+    let to_error = |message| command_error(command, message);
+
+    let value = value
+        .to_str()
+        .ok_or_else(|| to_error(format!("{} isn't valid UTF-8", value.to_string_lossy())))?;
+
+    shell.escape_in(value, quote).ok_or_else(|| {
+        to_error(format!(
+            "{value} can't be quoted safely for the shell, use --exec or the TAG_* environment variables instead"
+        ))
+    })
+}
+
+/// `build_shell_command` replaces the placeholders in a shell command with
+/// the values of a file, escaped for the given shell. Placeholders that are
+/// already inside quotes of the command are escaped for these quotes.
+fn build_shell_command(
+    command: &str,
+    file: &TaggedFile,
+    shell: ShellKind,
+) -> Result<String, Error> {
+    let mut built = String::new();
+    let mut quote = Some(Quote::None);
+
+    for part in expand_placeholders(command, &placeholders(file), &file.tags) {
+        match part {
+            Part::Text(text) => {
+                built.push_str(text);
+                quote = quote.and_then(|quote| shell.scan_quotes(text, quote));
+            }
+            Part::Value(value) => {
+                let quote = quote.ok_or_else(|| escaped_placeholder_error(command))?;
+                built.push_str(&escape_value(command, &value, shell, quote)?);
+            }
+        }
+    }
+
+    Ok(built)
+}
+
+/// `set_environment` exposes the placeholders of a file to a command as
+/// environment variables.
+fn set_environment(command: &mut Command, file: &TaggedFile) {
+    for placeholder in placeholders(file) {
+        command.env(placeholder.env, placeholder.value);
+    }
+}
+
+/// `options_shell` returns the shell of the options or, if none is
+/// configured, the default shell.
+fn options_shell(options: &CommandOptions) -> &Shell {
+    options.shell.as_ref().unwrap_or_else(|| default_shell())
+}
+
+/// `shell_command` creates a command that executes `command` in a shell.
+fn shell_command(command: &str, shell: &Shell) -> Command {
+    let kind = shell.kind;
+    let mut shell = Command::new(&shell.program);
+    shell.arg(kind.flag());
+
+    // std would quote the command again by the rules of programs, but `cmd`
+    // parses its command line itself
+    #[cfg(windows)]
+    if kind == ShellKind::Cmd {
+        std::os::windows::process::CommandExt::raw_arg(&mut shell, command);
+        return shell;
+    }
+
+    shell.arg(command);
+    shell
+}
+
+/// `read_in_background` reads a pipe of a child until it is closed.
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut content = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut content);
+        }
+        content
+    })
+}
+
+/// `wait_in_background` waits until a child exited without reaping it, so
+/// that its id and process group can't be reused before `Child::wait()`.
+#[cfg(unix)]
+fn wait_in_background(child: &Child) -> JoinHandle<()> {
+    let id = child.id() as libc::id_t;
+    thread::spawn(move || loop {
+        // SAFETY: waitid() only writes to the zeroed siginfo_t, WNOWAIT
+        // leaves the child to be reaped by `Child::wait()`
+        let result = unsafe {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            libc::waitid(libc::P_PID, id, &mut info, libc::WEXITED | libc::WNOWAIT)
+        };
+        if result == 0 || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            break;
+        }
+    })
+}
+
+/// `PROCESS_GROUPS` are the process groups of the running commands with a
+/// timeout. A slot is `0` if it is free and `-1` if it was reserved for a
+/// command that isn't running yet.
+#[cfg(unix)]
+static PROCESS_GROUPS: [AtomicI32; 256] = [const { AtomicI32::new(0) }; 256];
+
+/// `forward_signal` is the handler of signals that terminate `tag`. Commands
+/// in their own process group don't receive signals like Ctrl-C from the
+/// terminal, so the signal is forwarded to them before `tag` terminates.
+#[cfg(unix)]
+extern "C" fn forward_signal(signal: libc::c_int) {
+    for group in &PROCESS_GROUPS {
+        let pid = group.load(Ordering::SeqCst);
+        if pid > 0 {
+            // SAFETY: kill() is async-signal-safe and only sends a signal.
+            // Groups are unregistered as soon as their leader exited, so the
+            // signal reaches the processes of a command.
+            unsafe {
+                libc::kill(-pid, signal);
+            }
+        }
+    }
+
+    // SAFETY: signal() and raise() are async-signal-safe, restoring the
+    // default handler lets `tag` terminate like without a handler
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// `forward_signals()` installs handlers that forward `SIGINT`, `SIGTERM` and
+/// `SIGHUP` to the commands running with a timeout before the process
+/// terminates.
+///
+/// These commands run in their own process group, so without
+/// the handlers they don't receive signals like Ctrl-C from the terminal.
+/// Signals that are ignored, e.g. by `nohup`, stay ignored.
+#[cfg(unix)]
+pub fn forward_signals() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            // SAFETY: the handler only calls async-signal-safe functions
+            unsafe {
+                let handler = forward_signal as extern "C" fn(libc::c_int);
+                let previous = libc::signal(signal, handler as libc::sighandler_t);
+                if previous == libc::SIG_IGN {
+                    libc::signal(signal, libc::SIG_IGN);
+                }
+            }
+        }
+    });
+}
+
+/// `ProcessGroup` is a slot of `PROCESS_GROUPS` reserved for a command that
+/// is started in its own process group. The slot is freed when it's dropped.
+#[cfg(unix)]
+struct ProcessGroup(usize);
+
+#[cfg(unix)]
+impl ProcessGroup {
+    /// `reserve()` reserves a slot. Returns `None` if all slots are in use, in
+    /// which case the command has to stay in the process group of `tag`.
+    fn reserve() -> Option<Self> {
+        PROCESS_GROUPS
+            .iter()
+            .position(|group| {
+                group
+                    .compare_exchange(0, -1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })
+            .map(Self)
+    }
+
+    /// `set()` stores the id of the started command, which leads its own
+    /// process group.
+    fn set(&self, pid: i32) {
+        PROCESS_GROUPS[self.0].store(pid, Ordering::SeqCst);
+    }
+
+    /// `kill()` kills all processes of the process group.
+    fn kill(&self) {
+        let pid = PROCESS_GROUPS[self.0].load(Ordering::SeqCst);
+        if pid > 0 {
+            // SAFETY: kill() only sends a signal. The leader of the group
+            // wasn't reaped yet, so the group can't belong to other
+            // processes.
+            unsafe {
+                libc::kill(-pid, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        PROCESS_GROUPS[self.0].store(0, Ordering::SeqCst);
+    }
+}
+
+/// `kill` kills a child and, on Unix, all other processes in its process
+/// group, so that processes started by a shell are killed as well.
+fn kill(child: &mut Child, #[cfg(unix)] process_group: Option<&ProcessGroup>) {
+    #[cfg(unix)]
+    if let Some(process_group) = process_group {
+        process_group.kill();
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// `execute` executes a command and collects its output. If the command or
+/// the processes it started and that keep its output open run longer than
+/// the timeout of the options, they are killed and an `Error::Timeout` is
+/// returned. Commands never read from stdin, which may be the query or the
+/// searched paths, or the terminal in inspect mode.
+fn execute(
+    mut command: Command,
+    name: &str,
+    options: &CommandOptions,
+) -> Result<CommandResult, Error> {
+    let to_error = |source| Error::Command {
+        command: name.to_string(),
+        source,
+    };
+
+    command.stdin(Stdio::null());
+
+    let start = Instant::now();
+    let Some(timeout) = options.timeout else {
+        let output = command.output().map_err(to_error)?;
+        return Ok(CommandResult::new(&output, start.elapsed()));
+    };
+
+    // the command runs in its own process group, so that the processes it
+    // started can be killed with it
+    #[cfg(unix)]
+    let process_group = ProcessGroup::reserve();
+    #[cfg(unix)]
+    if process_group.is_some() {
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    }
 
-    let output = if cfg!(target_os = "windows") {
-        Command::new("cmd").arg("/C").arg(command.clone()).output()
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(to_error)?;
+
+    #[cfg(unix)]
+    if let (Some(process_group), Ok(pid)) = (&process_group, i32::try_from(child.id())) {
+        process_group.set(pid);
+    }
+
+    // the pipes are read while waiting, so that the child never blocks on a
+    // full pipe
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    #[cfg(unix)]
+    let leader = wait_in_background(&child);
+
+    // processes started in the background may keep the pipes open after the
+    // command exited, so they have to finish before the timeout as well
+    loop {
+        #[cfg(unix)]
+        let exited = leader.is_finished();
+        #[cfg(not(unix))]
+        let exited = child.try_wait().map_err(to_error)?.is_some();
+
+        if exited && stdout.is_finished() && stderr.is_finished() {
+            break;
+        }
+
+        if start.elapsed() >= timeout {
+            kill(
+                &mut child,
+                #[cfg(unix)]
+                process_group.as_ref(),
+            );
+            return Err(Error::Timeout {
+                command: name.to_string(),
+                timeout,
+            });
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let status = child.wait().map_err(to_error)?;
+    #[cfg(unix)]
+    drop(process_group);
+
+    let output = Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+
+    Ok(CommandResult::new(&output, start.elapsed()))
+}
+
+/// `build_batch_commands` replaces `#FILES#` in a shell command with the
+/// shell-escaped paths separated by spaces. If the command doesn't contain
+/// `#FILES#`, the paths are appended to it. Like placeholders of other
+/// commands, `#FILES#` inside quotes is escaped for these quotes. The paths
+/// are split across as many commands as needed to keep every command shorter
+/// than `limit` bytes.
+fn build_batch_commands(
+    command: &str,
+    paths: &[impl AsRef<Path>],
+    limit: usize,
+    shell: ShellKind,
+) -> Result<Vec<String>, Error> {
+    let template = if command.contains(FILES_PLACEHOLDER) {
+        command.to_string()
     } else {
-        Command::new("bash").arg("-c").arg(command.clone()).output()
+        format!("{command} {FILES_PLACEHOLDER}")
     };
+    let segments = template.split(FILES_PLACEHOLDER).collect::<Vec<_>>();
+    let base_length = segments.iter().map(|segment| segment.len()).sum::<usize>();
 
-    if let Err(e) = &output {
-        eprintln!(
-            "{} Wasn't able to execute command {}: {}",
-            "[ERROR]".red().bold(),
-            command.blue().underline(),
-            e.to_string().red()
-        );
+    // the quotes each occurrence of `#FILES#` is inside of
+    let mut quotes = Vec::new();
+    let mut quote = Quote::None;
+    for segment in &segments[..segments.len() - 1] {
+        quote = shell
+            .scan_quotes(segment, quote)
+            .ok_or_else(|| escaped_placeholder_error(command))?;
+        quotes.push(quote);
     }
 
-    let output = output.unwrap();
-    let output_string = std::str::from_utf8(output.stdout.as_slice());
+    let mut chunks: Vec<Vec<Vec<String>>> = Vec::new();
+    let mut chunk_length = 0;
+    for path in paths {
+        let escaped = quotes
+            .iter()
+            .map(|quote| escape_value(command, path.as_ref().as_os_str(), shell, *quote))
+            .collect::<Result<Vec<_>, _>>()?;
+        let length = escaped.iter().map(String::len).sum::<usize>();
 
-    if let Err(e) = &output_string {
-        eprintln!(
-            "{} Failed to get output from command {}: {}",
-            "[ERROR]".red().bold(),
-            command.blue().underline(),
-            e.to_string().red()
-        );
+        // a path that is too long on its own still gets a command of its own
+        match chunks.last_mut() {
+            Some(chunk) if base_length + chunk_length + quotes.len() + length <= limit => {
+                chunk_length += quotes.len() + length;
+                chunk.push(escaped);
+            }
+            _ => {
+                chunk_length = length;
+                chunks.push(vec![escaped]);
+            }
+        }
+    }
+
+    Ok(chunks
+        .iter()
+        .map(|chunk| {
+            let mut built = segments[0].to_string();
+            for (i, segment) in segments[1..].iter().enumerate() {
+                let paths = chunk.iter().map(|escaped| escaped[i].as_str());
+                built.push_str(&paths.collect::<Vec<_>>().join(" "));
+                built.push_str(segment);
+            }
+            built
+        })
+        .collect())
+}
+
+/// `substitute_args` replaces the placeholders and every `{}` in the
+/// arguments of a program with the values of a file. If no argument contains
+/// a placeholder, the path is passed as the last argument.
+fn substitute_args(args: &[String], file: &TaggedFile) -> Vec<OsString> {
+    let placeholders = placeholders(file);
+
+    let mut has_placeholder = false;
+    let mut substituted = args
+        .iter()
+        .map(|arg| {
+            let mut substituted = OsString::new();
+            // `{}` only exists for programs, so it isn't one of the placeholders
+            for (i, text) in arg.split(EXEC_PLACEHOLDER).enumerate() {
+                if i > 0 {
+                    has_placeholder = true;
+                    substituted.push(&file.path);
+                }
+
+                for part in expand_placeholders(text, &placeholders, &file.tags) {
+                    match part {
+                        Part::Text(text) => substituted.push(text),
+                        Part::Value(value) => {
+                            has_placeholder = true;
+                            substituted.push(value);
+                        }
+                    }
+                }
+            }
+            substituted
+        })
+        .collect::<Vec<_>>();
+
+    if !has_placeholder {
+        substituted.push(file.path.clone().into_os_string());
     }
 
-    output_string.unwrap().to_string()
+    substituted
 }
 
-/// `execute_filter_command_on_file` executes a command on a given #FILE# and returns
+/// `execute_command_on_file` executes a command on a given file and
+/// returns its result.
 ///
-/// # Panics
+/// Placeholders like `#FILE#` or `#TAGS#` are replaced with the values of
+/// the file, quoted for the shell of the options, so they are always passed
+/// as a single argument. The values are also available as environment
+/// variables like `TAG_FILE` or `TAG_TAGS`.
 ///
-/// This function panics if it fails to convert the given path to a `&str`.
-#[must_use]
-pub fn execute_filter_command_on_file(path: &Path, command: &str) -> bool {
-    let command = command.replace("#FILE#", path.to_str().unwrap());
+/// # Errors
+///
+/// This function errors if a used value isn't valid UTF-8 or can't be
+/// quoted for the shell, the command can't be executed or it runs longer
+/// than the timeout.
+pub fn execute_command_on_file(
+    file: &TaggedFile,
+    command: &str,
+    options: &CommandOptions,
+) -> Result<CommandResult, Error> {
+    let shell = options_shell(options);
+    let command = build_shell_command(command, file, shell.kind)?;
 
-    let output = if cfg!(target_os = "windows") {
-        Command::new("cmd").arg("/C").arg(command.clone()).output()
-    } else {
-        Command::new("bash").arg("-c").arg(command.clone()).output()
+    let mut shell = shell_command(&command, shell);
+    set_environment(&mut shell, file);
+
+    execute(shell, &command, options)
+}
+
+/// `execute_exec_on_file` executes a program with arguments on a given file
+/// without a shell and returns its result. The first element of `exec` is
+/// the program and the remaining ones its arguments.
+///
+/// Every `{}` and placeholder like `#FILE#` in the arguments is replaced with
+/// the value of the file. If no argument contains one, the path of the file
+/// is passed as the last argument.
+///
+/// # Errors
+///
+/// This function errors if no program is given, it can't be executed or it
+/// runs longer than the timeout.
+pub fn execute_exec_on_file(
+    file: &TaggedFile,
+    exec: &[String],
+    options: &CommandOptions,
+) -> Result<CommandResult, Error> {
+    let Some((program, args)) = exec.split_first() else {
+        return Err(Error::Command {
+            command: String::new(),
+            source: io::Error::new(io::ErrorKind::InvalidInput, "no program given"),
+        });
     };
 
-    if let Err(e) = &output {
-        eprintln!(
-            "{} Wasn't able to execute command {}: {}",
-            "[ERROR]".red().bold(),
-            command.blue().underline(),
-            e.to_string().red()
+    let mut command = Command::new(program);
+    command.args(substitute_args(args, file));
+    set_environment(&mut command, file);
+
+    execute(command, &exec.join(" "), options)
+}
+
+/// `execute_filter_command_on_file` executes a command on a given file and returns
+/// whether it ran successfully. Placeholders are replaced like in
+/// `execute_command_on_file`.
+///
+/// # Errors
+///
+/// This function errors if a used value isn't valid UTF-8 or can't be
+/// quoted for the shell, the command can't be executed or it runs longer
+/// than the timeout.
+pub fn execute_filter_command_on_file(
+    file: &TaggedFile,
+    command: &str,
+    options: &CommandOptions,
+) -> Result<bool, Error> {
+    Ok(execute_command_on_file(file, command, options)?.success())
+}
+
+/// `execute_batch_command` executes a shell command once with the paths of
+/// all given files and returns whether it ran successfully.
+///
+/// `#FILES#` is replaced with the shell-escaped paths, or the paths are
+/// appended if the command doesn't contain it. Like `xargs`, the command is
+/// executed multiple times if the paths don't fit into a single command. It
+/// isn't executed at all if there are no paths. The command inherits the
+/// standard input and output, so interactive programs like editors work, and
+/// the timeout of the options doesn't apply.
+///
+/// # Errors
+///
+/// This function errors if a path isn't valid UTF-8 or can't be quoted for
+/// the shell, or the command can't be executed.
+pub fn execute_batch_command(
+    paths: &[impl AsRef<Path>],
+    command: &str,
+    options: &CommandOptions,
+) -> Result<bool, Error> {
+    let shell = options_shell(options);
+    let mut success = true;
+
+    for command in build_batch_commands(command, paths, BATCH_COMMAND_LIMIT, shell.kind)? {
+        let status = shell_command(&command, shell)
+            .status()
+            .map_err(|source| Error::Command { command, source })?;
+        success &= status.success();
+    }
+
+    Ok(success)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    use super::{
+        build_batch_commands, build_shell_command, choose_shell, execute_batch_command,
+        execute_command_on_file, execute_exec_on_file, execute_filter_command_on_file,
+        substitute_args, CommandOptions, Quote, Shell, ShellKind,
+    };
+    use crate::search::TaggedFile;
+
+    /// `test_options` returns options executing commands with `sh`, so that
+    /// the tests don't depend on the shell of the user.
+    fn test_options() -> CommandOptions {
+        CommandOptions {
+            shell: Shell::new("sh"),
+            ..Default::default()
+        }
+    }
+
+    fn test_file(path: &str) -> TaggedFile {
+        TaggedFile {
+            path: PathBuf::from("notes").join(path),
+            root: PathBuf::from("notes"),
+            tags: vec!["#a".to_string(), "#b".to_string()],
+            inherited_tags: vec![],
+        }
+    }
+
+    #[test]
+    fn test_shell_escape() {
+        struct TestCase<'a> {
+            name: &'a str,
+            shell: ShellKind,
+            input: &'a str,
+            expected_output: Option<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_plain",
+                shell: ShellKind::Posix,
+                input: "notes/a.md",
+                expected_output: Some("'notes/a.md'"),
+            },
+            TestCase {
+                name: "success_special_characters",
+                shell: ShellKind::Posix,
+                input: "a b; rm -rf ~.md",
+                expected_output: Some("'a b; rm -rf ~.md'"),
+            },
+            TestCase {
+                name: "success_single_quote",
+                shell: ShellKind::Posix,
+                input: "it's.md",
+                expected_output: Some("'it'\\''s.md'"),
+            },
+            TestCase {
+                name: "success_fish",
+                shell: ShellKind::Fish,
+                input: "it's a\\b.md",
+                expected_output: Some("'it\\'s a\\\\b.md'"),
+            },
+            TestCase {
+                name: "success_cmd",
+                shell: ShellKind::Cmd,
+                input: "C:\\a & b\\",
+                expected_output: Some("\"C:\\a & b\\\\\""),
+            },
+            TestCase {
+                name: "fail_cmd_variable",
+                shell: ShellKind::Cmd,
+                input: "%PATH%.md",
+                expected_output: None,
+            },
+            TestCase {
+                name: "fail_cmd_quote",
+                shell: ShellKind::Cmd,
+                input: "a\" & calc & \".md",
+                expected_output: None,
+            },
+            TestCase {
+                name: "success_powershell",
+                shell: ShellKind::PowerShell,
+                input: "$(calc) it's \u{2019}.md",
+                expected_output: Some("'$(calc) it''s \u{2019}\u{2019}.md'"),
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_shell_escape: \n\t{}", test_case.name);
+
+            assert_eq!(
+                test_case.expected_output.map(str::to_string),
+                test_case.shell.escape(test_case.input)
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_build_shell_command() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_output: &'a str,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_file",
+                input: "cat #FILE#",
+                expected_output: "cat 'notes/sub/a b.md'",
+            },
+            TestCase {
+                name: "success_path_parts",
+                input: "#DIR# #NAME# #STEM# #EXT# #RELPATH# #ROOT#",
+                expected_output: "'notes/sub' 'a b.md' 'a b' 'md' 'sub/a b.md' 'notes'",
+            },
+            TestCase {
+                name: "success_tags",
+                input: "echo #TAGS# #TAG[1]# #TAG[2]#",
+                expected_output: "echo '#a #b' '#b' ''",
+            },
+            TestCase {
+                name: "success_no_placeholder",
+                input: "grep '#todo' #TAG[]# #TAG[x]#",
+                expected_output: "grep '#todo' #TAG[]# #TAG[x]#",
+            },
+            TestCase {
+                name: "success_exec_placeholder_ignored",
+                input: "find . -exec echo {} \\;",
+                expected_output: "find . -exec echo {} \\;",
+            },
+            TestCase {
+                name: "success_single_quoted",
+                input: "cat '#FILE#' 'x #NAME#'",
+                expected_output: "cat 'notes/sub/a b.md' 'x a b.md'",
+            },
+            TestCase {
+                name: "success_double_quoted",
+                input: "cat \"#DIR#/#NAME#\" \"it's\" #STEM#",
+                expected_output: "cat \"notes/sub/a b.md\" \"it's\" 'a b'",
+            },
+            TestCase {
+                name: "success_escaped_quote",
+                input: "echo \\'#NAME#\\'",
+                expected_output: "echo \\''a b.md'\\'",
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_build_shell_command: \n\t{}", test_case.name);
+
+            assert_eq!(
+                test_case.expected_output,
+                build_shell_command(test_case.input, &test_file("sub/a b.md"), ShellKind::Posix)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_shell_escape_in_quotes() {
+        struct TestCase<'a> {
+            name: &'a str,
+            shell: ShellKind,
+            command: &'a str,
+            value: &'a str,
+            expected_output: Option<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_posix_single",
+                shell: ShellKind::Posix,
+                command: "cat 'x",
+                value: "it's;.md",
+                expected_output: Some("it'\\''s;.md"),
+            },
+            TestCase {
+                name: "success_posix_double",
+                shell: ShellKind::Posix,
+                command: "cat \"x",
+                value: "\"$(id)`id`\\.md",
+                expected_output: Some("\\\"\\$(id)\\`id\\`\\\\.md"),
+            },
+            TestCase {
+                name: "success_posix_closed",
+                shell: ShellKind::Posix,
+                command: "cat 'x' \"y\" ",
+                value: "a b",
+                expected_output: Some("'a b'"),
+            },
+            TestCase {
+                name: "success_fish_single",
+                shell: ShellKind::Fish,
+                command: "cat 'x\\' ",
+                value: "it's\\",
+                expected_output: Some("it\\'s\\\\"),
+            },
+            TestCase {
+                name: "success_powershell_single",
+                shell: ShellKind::PowerShell,
+                command: "cat 'it''s ",
+                value: "it\u{2019}s",
+                expected_output: Some("it\u{2019}\u{2019}s"),
+            },
+            TestCase {
+                name: "success_powershell_double",
+                shell: ShellKind::PowerShell,
+                command: "cat \"",
+                value: "$(calc)`\".md",
+                expected_output: Some("`$(calc)```\".md"),
+            },
+            TestCase {
+                name: "success_cmd_double",
+                shell: ShellKind::Cmd,
+                command: "type \"",
+                value: "a & b\\",
+                expected_output: Some("a & b\\\\"),
+            },
+            TestCase {
+                name: "fail_cmd_double_variable",
+                shell: ShellKind::Cmd,
+                command: "type \"",
+                value: "%PATH%",
+                expected_output: None,
+            },
+            TestCase {
+                name: "fail_escape_character",
+                shell: ShellKind::Posix,
+                command: "cat \\",
+                value: "a.md",
+                expected_output: None,
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_shell_escape_in_quotes: \n\t{}", test_case.name);
+
+            let escaped = test_case
+                .shell
+                .scan_quotes(test_case.command, Quote::None)
+                .and_then(|quote| test_case.shell.escape_in(test_case.value, quote));
+
+            assert_eq!(test_case.expected_output.map(str::to_string), escaped);
+        }
+    }
+
+    #[test]
+    fn test_substitute_args() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: Vec<&'a str>,
+            expected_output: Vec<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_placeholder",
+                input: vec!["-l", "{}"],
+                expected_output: vec!["-l", "notes/a b.md"],
+            },
+            TestCase {
+                name: "success_inside_argument",
+                input: vec!["--file={}.bak", "--tags=#TAGS#"],
+                expected_output: vec!["--file=notes/a b.md.bak", "--tags=#a #b"],
+            },
+            TestCase {
+                name: "success_next_to_placeholder",
+                input: vec!["{}#NAME#{}"],
+                expected_output: vec!["notes/a b.mda b.mdnotes/a b.md"],
+            },
+            TestCase {
+                name: "success_no_placeholder",
+                input: vec!["-l"],
+                expected_output: vec!["-l", "notes/a b.md"],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_substitute_args: \n\t{}", test_case.name);
+
+            let args = test_case
+                .input
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                test_case.expected_output,
+                substitute_args(&args, &test_file("a b.md"))
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_build_batch_commands() {
+        struct TestCase<'a> {
+            name: &'a str,
+            command: &'a str,
+            paths: Vec<&'a str>,
+            limit: usize,
+            expected_output: Vec<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_placeholder",
+                command: "wc -l #FILES# | tail -n 1",
+                paths: vec!["a.md", "b c.md"],
+                limit: 1024,
+                expected_output: vec!["wc -l 'a.md' 'b c.md' | tail -n 1"],
+            },
+            TestCase {
+                name: "success_appended",
+                command: "tar -cf notes.tar",
+                paths: vec!["a.md"],
+                limit: 1024,
+                expected_output: vec!["tar -cf notes.tar 'a.md'"],
+            },
+            TestCase {
+                name: "success_chunked",
+                command: "ls",
+                paths: vec!["a.md", "b.md", "c.md"],
+                limit: "ls 'a.md' 'b.md'".len(),
+                expected_output: vec!["ls 'a.md' 'b.md'", "ls 'c.md'"],
+            },
+            TestCase {
+                name: "success_path_too_long",
+                command: "ls",
+                paths: vec!["a.md", "long.md"],
+                limit: "ls 'a.md'".len(),
+                expected_output: vec!["ls 'a.md'", "ls 'long.md'"],
+            },
+            TestCase {
+                name: "success_quoted",
+                command: "echo \"#FILES#\" #FILES#",
+                paths: vec!["a.md", "b c.md"],
+                limit: 1024,
+                expected_output: vec!["echo \"a.md b c.md\" 'a.md' 'b c.md'"],
+            },
+            TestCase {
+                name: "success_no_paths",
+                command: "ls",
+                paths: vec![],
+                limit: 1024,
+                expected_output: vec![],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_build_batch_commands: \n\t{}", test_case.name);
+
+            assert_eq!(
+                test_case.expected_output,
+                build_batch_commands(
+                    test_case.command,
+                    &test_case.paths,
+                    test_case.limit,
+                    ShellKind::Posix
+                )
+                .unwrap()
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_batch_command() {
+        assert!(execute_batch_command(
+            &["a.md", "b c.md"],
+            "set -- #FILES#; test $# -eq 2",
+            &test_options()
+        )
+        .unwrap());
+        assert!(!execute_batch_command(
+            &["a.md"],
+            "set -- #FILES#; test $# -eq 2",
+            &test_options()
+        )
+        .unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_on_file_with_special_characters() {
+        let file = test_file("a b; echo injected 'quoted'.md");
+        let options = test_options();
+
+        assert_eq!(
+            "notes/a b; echo injected 'quoted'.md\n",
+            execute_command_on_file(&file, "echo #FILE#", &options)
+                .unwrap()
+                .stdout
+        );
+        assert_eq!(
+            "notes/a b; echo injected 'quoted'.md\n",
+            execute_exec_on_file(&file, &["echo".to_string(), "{}".to_string()], &options)
+                .unwrap()
+                .stdout
         );
+        assert_eq!(
+            "#a #b a b; echo injected 'quoted'.md\n",
+            execute_command_on_file(&file, "echo \"$TAG_TAGS\" \"$TAG_NAME\"", &options)
+                .unwrap()
+                .stdout
+        );
+        assert!(execute_exec_on_file(&file, &[], &options).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_quoted_placeholder() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let pwned = temp_dir.path().join("PWNED");
+        let name = format!(
+            "x;touch {};\"$(touch {})\"'.md",
+            pwned.display(),
+            pwned.display()
+        );
+        let file = test_file(&name);
+        let options = test_options();
+
+        for command in [
+            "echo #FILE#",
+            "echo '#FILE#'",
+            "echo \"#FILE#\"",
+            "echo x'#DIR#/'\"#NAME#\"",
+        ] {
+            println!("test_execute_quoted_placeholder: \n\t{command}");
+
+            let result = execute_command_on_file(&file, command, &options).unwrap();
+
+            assert!(!pwned.exists());
+            assert_eq!(
+                format!("{}\n", file.path.display()),
+                result.stdout.trim_start_matches('x')
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_with_timeout() {
+        struct TestCase<'a> {
+            name: &'a str,
+            command: &'a str,
+            expected_timeout: bool,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_in_time",
+                command: "echo done",
+                expected_timeout: false,
+            },
+            TestCase {
+                name: "fail_timeout",
+                command: "sleep 10",
+                expected_timeout: true,
+            },
+            TestCase {
+                name: "fail_timeout_background_process",
+                command: "sleep 10 & wait",
+                expected_timeout: true,
+            },
+            TestCase {
+                name: "fail_timeout_output_kept_open",
+                command: "sleep 10 &",
+                expected_timeout: true,
+            },
+            TestCase {
+                name: "success_output_closed",
+                command: "sleep 1 >/dev/null 2>&1 &",
+                expected_timeout: false,
+            },
+            TestCase {
+                name: "success_no_input",
+                command: "cat",
+                expected_timeout: false,
+            },
+        ];
+
+        let options = CommandOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..test_options()
+        };
+
+        for test_case in test_cases {
+            println!("test_execute_with_timeout: \n\t{}", test_case.name);
+
+            let start = Instant::now();
+            let result = execute_command_on_file(&test_file("a.md"), test_case.command, &options);
+
+            assert!(start.elapsed() < Duration::from_secs(5));
+            assert_eq!(
+                test_case.expected_timeout,
+                matches!(result, Err(crate::error::Error::Timeout { .. }))
+            );
+        }
+
+        assert!(execute_filter_command_on_file(&test_file("a.md"), "sleep 10", &options).is_err());
     }
 
-    output.unwrap().status.success()
+    #[cfg(unix)]
+    #[test]
+    fn test_command_result() {
+        struct TestCase<'a> {
+            name: &'a str,
+            command: &'a str,
+            expected_stdout: &'a str,
+            expected_stderr: &'a str,
+            expected_code: i32,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success",
+                command: "echo out; echo err >&2",
+                expected_stdout: "out\n",
+                expected_stderr: "err\n",
+                expected_code: 0,
+            },
+            TestCase {
+                name: "success_failing_command",
+                command: "echo failed >&2; exit 3",
+                expected_stdout: "",
+                expected_stderr: "failed\n",
+                expected_code: 3,
+            },
+            TestCase {
+                name: "success_invalid_utf8",
+                command: "printf 'a\\377b'",
+                expected_stdout: "a\u{FFFD}b",
+                expected_stderr: "",
+                expected_code: 0,
+            },
+        ];
+
+        for options in [
+            test_options(),
+            CommandOptions {
+                timeout: Some(Duration::from_secs(5)),
+                ..test_options()
+            },
+        ] {
+            for test_case in &test_cases {
+                println!("test_command_result: \n\t{}", test_case.name);
+
+                let result =
+                    execute_command_on_file(&test_file("a.md"), test_case.command, &options)
+                        .unwrap();
+
+                assert_eq!(test_case.expected_stdout, result.stdout);
+                assert_eq!(test_case.expected_stderr, result.stderr);
+                assert_eq!(Some(test_case.expected_code), result.status.code());
+                assert_eq!(test_case.expected_code == 0, result.success());
+                assert!(result.summary().starts_with(&format!(
+                    "exited with code {} after ",
+                    test_case.expected_code
+                )));
+            }
+        }
+    }
+
+    #[test]
+    fn test_choose_shell() {
+        struct TestCase<'a> {
+            name: &'a str,
+            environment: Option<&'a str>,
+            has_bash: bool,
+            expected_output: &'a str,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_environment",
+                environment: Some("/usr/bin/fish"),
+                has_bash: true,
+                expected_output: "/usr/bin/fish",
+            },
+            TestCase {
+                name: "success_bash",
+                environment: None,
+                has_bash: true,
+                expected_output: "bash",
+            },
+            TestCase {
+                name: "success_empty_environment",
+                environment: Some(""),
+                has_bash: true,
+                expected_output: "bash",
+            },
+            TestCase {
+                name: "success_sh_without_bash",
+                environment: None,
+                has_bash: false,
+                expected_output: "sh",
+            },
+            TestCase {
+                name: "success_unknown_environment",
+                environment: Some("/usr/bin/nu"),
+                has_bash: true,
+                expected_output: "bash",
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_choose_shell: \n\t{}", test_case.name);
+
+            assert_eq!(
+                OsString::from(test_case.expected_output),
+                choose_shell(test_case.environment.map(OsString::from), || {
+                    test_case.has_bash
+                })
+                .program
+            );
+        }
+    }
+
+    #[test]
+    fn test_shell_flag() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_output: Option<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_posix",
+                input: "/bin/zsh",
+                expected_output: Some("-c"),
+            },
+            TestCase {
+                name: "success_fish",
+                input: "/usr/bin/fish",
+                expected_output: Some("-c"),
+            },
+            TestCase {
+                name: "success_cmd",
+                input: "cmd.exe",
+                expected_output: Some("/C"),
+            },
+            TestCase {
+                name: "success_powershell",
+                input: "pwsh",
+                expected_output: Some("-Command"),
+            },
+            TestCase {
+                name: "fail_unknown",
+                input: "/usr/bin/nu",
+                expected_output: None,
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_shell_flag: \n\t{}", test_case.name);
+
+            assert_eq!(
+                test_case.expected_output,
+                ShellKind::from_program(test_case.input.as_ref()).map(ShellKind::flag)
+            );
+        }
+    }
 }
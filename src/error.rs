@@ -0,0 +1,209 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::parsers::{onfile, searchquery};
+use crate::sources::DecodeError;
+
+/// `Error` is the error returned by the functions of this library.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Walking a directory failed.
+    Walk(ignore::Error),
+    /// A glob or file type of the search filters is invalid.
+    Filter(ignore::Error),
+    /// Reading or writing failed.
+    Io(io::Error),
+    /// Accessing the file or directory at `path` failed.
+    File { path: PathBuf, source: io::Error },
+    /// The content of a file isn't text.
+    Decode(DecodeError),
+    /// A tagline can't be parsed.
+    Tagline(Box<pest::error::Error<onfile::Rule>>),
+    /// A line of a `.tags` manifest has no `:` separating the file name
+    /// from its tags.
+    Manifest(String),
+    /// A search query can't be parsed.
+    Query(Box<pest::error::Error<searchquery::Rule>>),
+    /// A command couldn't be executed.
+    Command { command: String, source: io::Error },
+    /// A command ran longer than the timeout and was killed.
+    Timeout { command: String, timeout: Duration },
+    /// Watching directories for changes failed.
+    Watch(notify::Error),
+    /// The thread pool reading files in parallel can't be created.
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// A custom tag source failed.
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Walk(_) => write!(f, "failed to walk directory"),
+            Self::Filter(_) => write!(f, "invalid filter"),
+            Self::Io(_) => write!(f, "failed to access file"),
+            Self::File { path, .. } => write!(f, "failed to access {}", path.display()),
+            Self::Decode(e) => write!(f, "{e}"),
+            Self::Tagline(_) => write!(f, "malformed tagline"),
+            Self::Manifest(line) => write!(f, "missing ':' in manifest line {line:?}"),
+            Self::Query(_) => write!(f, "invalid query"),
+            Self::Command { command, .. } => write!(f, "failed to execute command {command:?}"),
+            Self::Timeout { command, timeout } => {
+                write!(f, "command {command:?} timed out after {timeout:?}")
+            }
+            Self::Watch(_) => write!(f, "failed to watch for changes"),
+            Self::ThreadPool(_) => write!(f, "failed to create thread pool"),
+            Self::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Walk(e) | Self::Filter(e) => Some(e),
+            Self::Io(e) | Self::File { source: e, .. } | Self::Command { source: e, .. } => Some(e),
+            Self::Decode(_) | Self::Manifest(_) | Self::Timeout { .. } => None,
+            Self::Tagline(e) => Some(e),
+            Self::Query(e) => Some(e),
+            Self::Watch(e) => Some(e),
+            Self::ThreadPool(e) => Some(e),
+            Self::Other(e) => e.source(),
+        }
+    }
+}
+
+impl From<ignore::Error> for Error {
+    fn from(e: ignore::Error) -> Self {
+        Self::Walk(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl Error {
+    /// `file()` returns a function that adds the path of the file or
+    /// directory an I/O error occurred at, for use with `map_err()`.
+    pub(crate) fn file(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::File {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<pest::error::Error<onfile::Rule>> for Error {
+    fn from(e: pest::error::Error<onfile::Rule>) -> Self {
+        Self::Tagline(Box::new(e))
+    }
+}
+
+impl From<pest::error::Error<searchquery::Rule>> for Error {
+    fn from(e: pest::error::Error<searchquery::Rule>) -> Self {
+        Self::Query(Box::new(e))
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(e: notify::Error) -> Self {
+        Self::Watch(e)
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(e: rayon::ThreadPoolBuildError) -> Self {
+        Self::ThreadPool(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::io;
+
+    use pest::Parser;
+
+    use super::Error;
+    use crate::parsers::searchquery::{QueryParser, Rule};
+    use crate::sources::DecodeError;
+
+    #[test]
+    fn test_error_source_chain() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: Error,
+            expected_message: &'a str,
+            expected_source: bool,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_io",
+                input: io::Error::from(io::ErrorKind::NotFound).into(),
+                expected_message: "failed to access file: entity not found",
+                expected_source: true,
+            },
+            TestCase {
+                name: "success_file",
+                input: Error::File {
+                    path: "notes/a.md".into(),
+                    source: io::Error::from(io::ErrorKind::NotFound),
+                },
+                expected_message: "failed to access notes/a.md: entity not found",
+                expected_source: true,
+            },
+            TestCase {
+                name: "success_query",
+                input: QueryParser::parse(Rule::tagsearch, "#a &")
+                    .unwrap_err()
+                    .into(),
+                expected_message: "invalid query: ",
+                expected_source: true,
+            },
+            TestCase {
+                name: "success_command",
+                input: Error::Command {
+                    command: "false".to_string(),
+                    source: io::Error::from(io::ErrorKind::PermissionDenied),
+                },
+                expected_message: "failed to execute command \"false\": permission denied",
+                expected_source: true,
+            },
+            TestCase {
+                name: "success_decode",
+                input: DecodeError.into(),
+                expected_message: "content can't be decoded as text",
+                expected_source: false,
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_error_source_chain: \n\t{}", test_case.name);
+
+            let mut message = test_case.input.to_string();
+            if let Some(source) = test_case.input.source() {
+                message = format!("{message}: {source}");
+            }
+
+            assert_eq!(
+                test_case.expected_source,
+                test_case.input.source().is_some()
+            );
+            assert!(message.starts_with(test_case.expected_message));
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use pest::Parser;
+
+use crate::parsers::onfile::{self, TaglineParser};
+
+/// `to_tag()` turns a bare tag name into a tag as used in taglines.
+/// It returns `None` if the name isn't a valid tag.
+pub(crate) fn to_tag(name: &str) -> Option<String> {
+    let tag = format!("#{name}");
+    let parsed = TaglineParser::parse(onfile::Rule::tag, &tag).ok()?;
+
+    if parsed.as_str() == tag {
+        Some(tag)
+    } else {
+        None
+    }
+}
+
+/// `push_tags()` adds all valid tags of a list of names to `tags`
+/// if they aren't already contained in it.
+fn push_tags<'a>(tags: &mut Vec<String>, names: impl Iterator<Item = &'a str>) {
+    for tag in names.filter_map(to_tag) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+}
+
+/// `get_org_tags()` returns the tags of an Org document.
+/// Tags are read from `#+FILETAGS:` lines and from heading tags
+/// like `* Heading :tag1:tag2:`.
+#[must_use]
+pub fn get_org_tags(content: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim_end();
+
+        if line
+            .get(..11)
+            .is_some_and(|start| start.eq_ignore_ascii_case("#+filetags:"))
+        {
+            push_tags(
+                &mut tags,
+                line[11..].split(|c: char| c == ':' || c.is_whitespace()),
+            );
+            continue;
+        }
+
+        let stars = line.len() - line.trim_start_matches('*').len();
+        if stars == 0 || !line[stars..].starts_with(' ') {
+            continue;
+        }
+
+        let Some(last_word) = line[stars..].split_whitespace().last() else {
+            continue;
+        };
+
+        if last_word.len() > 1 && last_word.starts_with(':') && last_word.ends_with(':') {
+            push_tags(&mut tags, last_word.split(':'));
+        }
+    }
+
+    tags
+}
+
+/// `get_asciidoc_tags()` returns the tags of an `AsciiDoc` document.
+/// Tags are read from the comma separated `:keywords:` attribute
+/// in the document header.
+#[must_use]
+pub fn get_asciidoc_tags(content: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    // the header ends at the first empty line after its start
+    for line in content.lines().skip_while(|line| line.trim().is_empty()) {
+        if line.trim().is_empty() {
+            break;
+        }
+
+        if let Some(keywords) = line.strip_prefix(":keywords:") {
+            push_tags(&mut tags, keywords.split(',').map(str::trim));
+        }
+    }
+
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_asciidoc_tags, get_org_tags};
+
+    #[test]
+    fn test_get_org_tags() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_tags: Vec<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_filetags",
+                input: "#+TITLE: Notes\n#+FILETAGS: :work:project_x:\n\nText",
+                expected_tags: vec!["#work", "#project_x"],
+            },
+            TestCase {
+                name: "success_heading_tags",
+                input: "* Heading :a:b:\n** Sub heading    :c:\n* No tags\nText :d:",
+                expected_tags: vec!["#a", "#b", "#c"],
+            },
+            TestCase {
+                name: "success_skip_invalid",
+                input: "#+filetags: :ok:not+ok:\n*bold* :text:\nümlauts",
+                expected_tags: vec!["#ok"],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_get_org_tags: \n\t{}", test_case.name);
+
+            assert_eq!(test_case.expected_tags, get_org_tags(test_case.input));
+        }
+    }
+
+    #[test]
+    fn test_get_asciidoc_tags() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_tags: Vec<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_keywords",
+                input: "= Title\n:author: someone\n:keywords: work, project-x,notes\n\nText",
+                expected_tags: vec!["#work", "#project-x", "#notes"],
+            },
+            TestCase {
+                name: "success_outside_header",
+                input: "= Title\n\n:keywords: work",
+                expected_tags: vec![],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_get_asciidoc_tags: \n\t{}", test_case.name);
+
+            assert_eq!(test_case.expected_tags, get_asciidoc_tags(test_case.input));
+        }
+    }
+}
@@ -13,7 +13,9 @@ use tui_textarea::{Input, Key, TextArea};
 
 use crate::search::TaggedFile;
 
-use crate::commands::execute_command_on_file;
+use crate::commands::{execute_command_on_file, CommandOptions, CommandResult};
+use crate::error::Error;
+use crate::sources::{read_text, text_reader};
 
 /// `InteractiveInputs` contains possible inputs for interactive mode.
 #[derive(Default)]
@@ -30,7 +32,11 @@ struct InteractiveInputs {
 /// # Errors
 ///
 /// This function returns an error if rendering or handling inputs fails.
-pub fn interactive_output(files: &[TaggedFile], command_outputs: &[String]) -> io::Result<()> {
+pub fn interactive_output(
+    files: &[TaggedFile],
+    command_results: &[Option<Result<CommandResult, Error>>],
+    command_options: &CommandOptions,
+) -> io::Result<()> {
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
 
     // the command textarea
@@ -48,7 +54,8 @@ pub fn interactive_output(files: &[TaggedFile], command_outputs: &[String]) -> i
     let mut interactive_inputs = InteractiveInputs::default();
     while !interactive_inputs.quit {
         let file = &files[interactive_inputs.file_index];
-        let command_output = command_outputs[interactive_inputs.file_index].clone();
+        let command_output =
+            command_output_text(command_results[interactive_inputs.file_index].as_ref());
 
         terminal.draw(|frame| {
             interactive_output_ui(
@@ -56,6 +63,7 @@ pub fn interactive_output(files: &[TaggedFile], command_outputs: &[String]) -> i
                 command_output.as_str(),
                 &mut interactive_inputs,
                 &mut textarea,
+                command_options,
                 frame,
             );
         })?;
@@ -70,12 +78,37 @@ pub fn interactive_output(files: &[TaggedFile], command_outputs: &[String]) -> i
     Ok(())
 }
 
+/// `command_output_text` formats the result of the command executed on a
+/// file for the "Command Output" tab.
+fn command_output_text(command_result: Option<&Result<CommandResult, Error>>) -> String {
+    match command_result {
+        None => String::new(),
+        Some(Err(e)) => {
+            let mut text = format!("Failed to execute command: {e}");
+            let mut source = std::error::Error::source(e);
+            while let Some(e) = source {
+                text = format!("{text}: {e}");
+                source = e.source();
+            }
+            text
+        }
+        Some(Ok(result)) => {
+            let mut text = result.stdout.clone();
+            if !result.stderr.is_empty() {
+                text = format!("{text}\n--- error output ---\n{}", result.stderr);
+            }
+            format!("{text}\n--- command {} ---", result.summary())
+        }
+    }
+}
+
 /// `interactive_output_ui` renders the UI.
 fn interactive_output_ui(
     file: &TaggedFile,
     command_output: &str,
     interactive_inputs: &mut InteractiveInputs,
     text_area: &mut TextArea,
+    command_options: &CommandOptions,
     frame: &mut Frame,
 ) {
     let area = layout(frame.size(), Direction::Vertical, &[1, 0, 1]);
@@ -94,7 +127,8 @@ fn interactive_output_ui(
     render_help_menu(area[2], frame);
 
     if interactive_inputs.command_mode {
-        interactive_inputs.command_mode = command_mode_input(file, text_area).unwrap();
+        interactive_inputs.command_mode =
+            command_mode_input(file, text_area, command_options).unwrap();
         command_mode_render(text_area, frame);
     }
 }
@@ -120,12 +154,18 @@ fn render_tab_content(
     frame: &mut Frame,
 ) {
     let content = match tab_index {
-        0 => std::fs::read_to_string(&file.path).unwrap(),
+        0 => std::fs::File::open(&file.path)
+            .map_err(Into::into)
+            .and_then(|file| read_text(&mut text_reader(file)))
+            .unwrap_or_else(|e| format!("Failed to read file: {e}")),
         1 => command_output.to_string(),
         2 => {
             let mut out_string = String::new();
             for tag in &file.tags {
                 out_string += tag.as_str();
+                if file.inherited_tags.contains(tag) {
+                    out_string += " (inherited)";
+                }
                 out_string.push('\n');
             }
             out_string
@@ -272,7 +312,11 @@ fn command_mode_render(text_area: &mut TextArea, frame: &mut Frame) {
 }
 
 /// `command_mode_input` handles inputs in command mode.
-fn command_mode_input(file: &TaggedFile, text_area: &mut TextArea) -> Result<bool, std::io::Error> {
+fn command_mode_input(
+    file: &TaggedFile,
+    text_area: &mut TextArea,
+    command_options: &CommandOptions,
+) -> Result<bool, std::io::Error> {
     match crossterm::event::read()?.into() {
         Input { key: Key::Esc, .. } => {
             return Ok(false);
@@ -280,7 +324,7 @@ fn command_mode_input(file: &TaggedFile, text_area: &mut TextArea) -> Result<boo
         Input {
             key: Key::Enter, ..
         } => {
-            let _ = execute_command_on_file(&file.path, &text_area.lines()[0]);
+            let _ = execute_command_on_file(file, &text_area.lines()[0], command_options);
             return Ok(false);
         }
         Input {
@@ -1,9 +1,28 @@
+/// error contains the error type of this library.
+pub mod error;
+pub use error::Error;
+
 /// parsers contains the relevant grammar parsers
 pub mod parsers;
 
+/// formats contains tag parsers for the native syntax of other file formats.
+pub mod formats;
+
+/// attributes contains the extended attribute tag storage.
+pub mod attributes;
+
+/// sources contains the sources tags of files are read from.
+pub mod sources;
+
+/// cache contains the persistent cache of the tags of searched files.
+pub mod cache;
+
 /// search contains functions for searching files and tags.
 pub mod search;
 
+/// watch contains a watcher for changes of searched files.
+pub mod watch;
+
 /// interactive inspection ui
 pub mod inspect;
 
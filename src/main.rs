@@ -1,24 +1,37 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
 use std::io::{stdout, BufRead, IsTerminal};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
 
 use colored::Colorize;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
 use crossterm::ExecutableCommand;
-use pest::Parser;
+use pest::iterators::Pairs;
 
-use tag::commands::{execute_command_on_file, execute_filter_command_on_file};
+use tag::attributes::add_xattr_tags;
+use tag::cache::default_cache_dir;
+use tag::commands::{
+    execute_batch_command, execute_command_on_file, execute_exec_on_file,
+    execute_filter_command_on_file, CommandOptions, CommandResult,
+};
 use tag::inspect;
-use tag::search::TaggedFile;
+use tag::search::{Changes, IndexError, TaggedFile, TaggedFiles, Warning};
+use tag::watch::IndexWatcher;
 use tag::{
-    parsers::searchquery::{construct_query_ast, evaluate_ast, QueryParser, Rule},
-    search::get_tags_from_files,
+    parsers::searchquery::{construct_query_ast, evaluate_ast, parse_query, Rule},
+    search::{get_tags_from_roots, SearchOptions},
 };
 
 mod cli {
+    use std::time::Duration;
+
     use clap::Parser;
+    use tag::commands::Shell;
 
-    #[derive(Parser)]
+    #[derive(Parser, Clone)]
     #[command(author, version, about, long_about = None)]
     #[allow(clippy::struct_excessive_bools)]
     pub struct Cli {
@@ -26,6 +39,14 @@ mod cli {
         /// The path that will be searched.
         pub path: String,
 
+        #[arg(long, value_name = "PATH")]
+        /// An additional path that will be searched. Can be used multiple times.
+        pub root: Vec<String>,
+
+        #[arg(long, value_name = "FILE")]
+        /// Read additional paths to search from a file, one per line ("-" reads from stdin).
+        pub roots_from: Option<String>,
+
         #[clap(value_name = "QUERY", group = "q-input")]
         /// Search query for the tags.
         pub query: Option<String>,
@@ -38,10 +59,39 @@ mod cli {
         /// A command that will be executed on matched files.
         pub command: Option<String>,
 
+        #[arg(
+            long,
+            value_name = "PROGRAM",
+            num_args = 1..,
+            allow_hyphen_values = true,
+            value_terminator = ";",
+            conflicts_with = "command"
+        )]
+        /// A program that will be executed on matched files without a shell. The arguments
+        /// end at ";" and {} is replaced with the path of the file.
+        pub exec: Vec<String>,
+
+        #[arg(long, value_name = "COMMAND", conflicts_with = "inspect")]
+        /// A command that will be executed once with the paths of all matched files. #FILES# is
+        /// replaced with the paths, which are appended if it is missing.
+        pub command_batch: Option<String>,
+
         #[arg(short, long)]
         /// A command that must run successfully for a file to be accepted.
         pub filter_command: Option<String>,
 
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        /// Kill filter commands and commands running longer than DURATION, e.g. 500ms, 10s or 2m.
+        pub command_timeout: Option<Duration>,
+
+        #[arg(long, conflicts_with = "inspect")]
+        /// Stop as soon as a command fails or can't be executed.
+        pub fail_fast: bool,
+
+        #[arg(long, value_name = "SHELL", env = "TAG_SHELL", value_parser = parse_shell)]
+        /// The shell executing commands. Defaults to $SHELL, bash or sh (cmd on Windows).
+        pub shell: Option<Shell>,
+
         #[arg(short, long)]
         /// Disable coloring.
         pub no_color: bool,
@@ -53,6 +103,82 @@ mod cli {
         #[arg(short, long, group = "output")]
         /// Enter an interactive inspection mode to view each file individually.
         pub inspect: bool,
+
+        #[arg(long)]
+        /// Also collect #hashtags found anywhere in the body of a file.
+        pub inline_tags: bool,
+
+        #[arg(long)]
+        /// Also collect tags stored in the user.xdg.tags extended attribute.
+        pub xattr: bool,
+
+        #[arg(long)]
+        /// Add the tags of matched files to the user.xdg.tags extended attribute (implies --xattr).
+        pub write_xattr: bool,
+
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        /// The amount of threads reading files in parallel (0 uses one thread per CPU).
+        pub threads: usize,
+
+        #[arg(short, long, value_name = "N", default_value_t = 1)]
+        /// The amount of filter commands and commands executed in parallel (0 runs one per CPU).
+        pub jobs: usize,
+
+        #[arg(long)]
+        /// Don't skip files matched by .gitignore, .ignore and .tagignore files.
+        pub no_ignore: bool,
+
+        #[arg(long)]
+        /// Don't skip hidden files and directories.
+        pub hidden: bool,
+
+        #[arg(long)]
+        /// Don't follow symbolic links.
+        pub no_follow: bool,
+
+        #[arg(long, value_name = "GLOB")]
+        /// Only search files matching this glob. Can be used multiple times.
+        pub include: Vec<String>,
+
+        #[arg(long, value_name = "GLOB")]
+        /// Skip files and directories matching this glob. Can be used multiple times.
+        pub exclude: Vec<String>,
+
+        #[arg(long = "type", value_name = "TYPES", value_delimiter = ',')]
+        /// Only search files of these comma separated types, e.g. md,txt.
+        pub types: Vec<String>,
+
+        #[arg(long, value_name = "N")]
+        /// Don't descend into directories deeper than N.
+        pub max_depth: Option<usize>,
+
+        #[arg(long, value_name = "N")]
+        /// Skip files less deep than N.
+        pub min_depth: Option<usize>,
+
+        #[arg(long, value_name = "SIZE", value_parser = parse_filesize)]
+        /// Skip files larger than SIZE, e.g. 512K or 10M.
+        pub max_filesize: Option<u64>,
+
+        #[arg(long, conflicts_with = "inspect")]
+        /// Keep running and print files that start or stop matching when files change.
+        pub watch: bool,
+
+        #[arg(long)]
+        /// Don't use or update the cache of previously read tags.
+        pub no_cache: bool,
+
+        #[arg(long, conflicts_with = "no_cache")]
+        /// Read all files again instead of using the cache of previously read tags.
+        pub rebuild_cache: bool,
+
+        #[arg(long)]
+        /// Print files that were skipped because they couldn't be read.
+        pub warnings: bool,
+
+        #[arg(long)]
+        /// Fail if any file was skipped because it couldn't be read.
+        pub strict: bool,
     }
 
     impl Cli {
@@ -60,148 +186,801 @@ mod cli {
             Self::parse()
         }
     }
+
+    /// `parse_duration` parses a duration in seconds with an optional
+    /// ms, s or m suffix.
+    fn parse_duration(duration: &str) -> Result<Duration, String> {
+        let duration = duration.trim().to_ascii_lowercase();
+
+        let unit = duration.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let (number, unit) = duration.split_at(unit.len());
+
+        let factor = match unit {
+            "ms" => 0.001,
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            _ => return Err(format!("invalid duration {duration:?}")),
+        };
+
+        number
+            .parse::<f64>()
+            .ok()
+            .and_then(|number| Duration::try_from_secs_f64(number * factor).ok())
+            .filter(|duration| !duration.is_zero())
+            .ok_or_else(|| format!("invalid duration {duration:?}"))
+    }
+
+    /// `parse_shell` parses a shell whose quoting is known.
+    fn parse_shell(shell: &str) -> Result<Shell, String> {
+        Shell::new(shell).ok_or_else(|| {
+            format!(
+                "unknown shell {shell:?}, use a POSIX shell like sh or bash, fish, PowerShell or cmd"
+            )
+        })
+    }
+
+    /// `parse_filesize` parses a size in bytes with an optional
+    /// K, M or G suffix.
+    fn parse_filesize(size: &str) -> Result<u64, String> {
+        let size = size.trim().to_ascii_uppercase();
+        let size = size.strip_suffix('B').unwrap_or(&size);
+
+        let (number, factor) = match size.chars().last() {
+            Some('K') => (&size[..size.len() - 1], 1 << 10),
+            Some('M') => (&size[..size.len() - 1], 1 << 20),
+            Some('G') => (&size[..size.len() - 1], 1 << 30),
+            _ => (size, 1),
+        };
+
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(factor))
+            .ok_or_else(|| format!("invalid size {size:?}"))
+    }
 }
 
-fn non_interactive_output(file: &TaggedFile, command_output: &str) {
-    println!("\t{}", format!("tags: {:?}", file.tags).blue());
+fn non_interactive_output(file: &TaggedFile, command_result: Option<&CommandResult>) {
+    println!("\t{}", format!("tags: {:?}", file.own_tags()).blue());
+
+    if !file.inherited_tags.is_empty() {
+        println!(
+            "\t{}",
+            format!("inherited tags: {:?}", file.inherited_tags).cyan()
+        );
+    }
+
+    let Some(result) = command_result else {
+        return;
+    };
 
-    if !command_output.is_empty() {
+    if !result.stdout.is_empty() {
         println!(
             "\tOutput of command:\n{}",
-            textwrap::indent(command_output, "\t\t")
+            textwrap::indent(&result.stdout, "\t\t")
+        );
+    }
+
+    if !result.stderr.is_empty() {
+        println!(
+            "\tError output of command:\n{}",
+            textwrap::indent(&result.stderr, "\t\t").yellow()
         );
     }
+
+    let summary = format!("Command {}", result.summary());
+    if result.success() {
+        println!("\t{summary}");
+    } else {
+        println!("\t{}", summary.red());
+    }
+}
+
+/// `error_chain` joins the message of an error with the messages of the
+/// errors that caused it.
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message = format!("{message}: {e}");
+        source = e.source();
+    }
+
+    message
 }
 
-fn log_error(msg: &str, e: Box<dyn std::error::Error>) {
+fn log_error(msg: &str, e: &dyn std::error::Error) {
+    // print the whole chain of errors that caused the error
     eprintln!(
         "{} {} {}",
         "[ERROR]".red().bold(),
         msg.red(),
-        e.to_string().red().underline()
+        error_chain(e).red().underline()
     );
 }
 
-fn main() {
-    let mut args = cli::Cli::new_and_parse();
-
-    // detect if output is in a terminal or not
-    if !stdout().is_terminal() {
-        args.silent = true;
-        args.no_color = true;
+/// `fetch_query` returns the query given as an argument or through stdin.
+fn fetch_query(args: &cli::Cli) -> String {
+    if !args.query_stdin && args.query.is_none() {
+        eprintln!(
+            "{} {}",
+            "[ERROR]".red().bold(),
+            "Please provide a query, either through stdin or by manually adding it.".red()
+        );
+        std::process::exit(1);
     }
 
-    if args.no_color {
-        colored::control::set_override(false);
+    args.query.clone().unwrap_or_else(|| {
+        let mut query = String::new();
+        let read_result = std::io::stdin().lock().read_line(&mut query);
+        if let Err(e) = read_result {
+            log_error("Failed to read query from stdin:", &e);
+            std::process::exit(1);
+        }
+        query
+    })
+}
+
+/// `report_warnings` prints the warnings of the file index. In strict mode,
+/// the program exits if there are any warnings.
+fn report_warnings(warnings: &[Warning], args: &cli::Cli) {
+    if warnings.is_empty() {
+        return;
     }
 
-    if !args.query_stdin && args.query.is_none() {
+    print_warnings(warnings, args);
+
+    if args.strict {
         eprintln!(
             "{} {}",
             "[ERROR]".red().bold(),
-            "Please provide a query, either through stdin or by manually adding it.".red()
+            "Files couldn't be indexed in strict mode.".red()
         );
         std::process::exit(1);
     }
+}
+
+/// `print_warnings` prints the warnings, or only their number unless
+/// `--warnings` or `--strict` is set.
+fn print_warnings(warnings: &[Warning], args: &cli::Cli) {
+    if warnings.is_empty() {
+        return;
+    }
 
-    // fetch the query
-    let query = if args.query.is_some() {
-        args.query.unwrap()
+    if args.warnings || args.strict {
+        for warning in warnings {
+            eprintln!(
+                "{} {}",
+                "[WARNING]".yellow().bold(),
+                warning.to_string().yellow()
+            );
+        }
     } else {
-        let mut query = String::new();
-        if let Err(e) = std::io::stdin().lock().read_line(&mut query) {
-            log_error("Failed to read query from stdin:", Box::new(e));
+        eprintln!(
+            "{} {}",
+            "[WARNING]".yellow().bold(),
+            format!(
+                "{} files couldn't be indexed, use --warnings to show them.",
+                warnings.len()
+            )
+            .yellow()
+        );
+    }
+}
+
+/// `fetch_roots` returns all paths that will be searched.
+fn fetch_roots(args: &cli::Cli) -> Vec<String> {
+    let mut roots = vec![args.path.clone()];
+    roots.extend(args.root.iter().cloned());
+
+    let Some(roots_from) = &args.roots_from else {
+        return roots;
+    };
+
+    let content = if roots_from == "-" {
+        if args.query_stdin {
+            eprintln!(
+                "{} {}",
+                "[ERROR]".red().bold(),
+                "The query and the paths can't both be read from stdin.".red()
+            );
             std::process::exit(1);
         }
-        query
+        std::io::read_to_string(std::io::stdin().lock())
+    } else {
+        std::fs::read_to_string(roots_from)
     };
 
-    let file_index = match get_tags_from_files(args.path.as_str()) {
+    match content {
+        Ok(content) => roots.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string),
+        ),
+        Err(e) => {
+            log_error("Failed to read paths:", &e);
+            std::process::exit(1);
+        }
+    }
+
+    roots
+}
+
+/// `search_options` builds the options of the search from the arguments.
+fn search_options(args: &cli::Cli) -> SearchOptions {
+    SearchOptions {
+        inline_tags: args.inline_tags,
+        xattr_tags: args.xattr || args.write_xattr,
+        threads: args.threads,
+        no_ignore: args.no_ignore,
+        hidden: args.hidden,
+        no_follow: args.no_follow,
+        include: args.include.clone(),
+        exclude: args.exclude.clone(),
+        types: args.types.clone(),
+        max_depth: args.max_depth,
+        min_depth: args.min_depth,
+        max_filesize: args.max_filesize,
+        cache: if args.no_cache {
+            None
+        } else {
+            default_cache_dir()
+        },
+        rebuild_cache: args.rebuild_cache,
+        ..Default::default()
+    }
+}
+
+/// `command_options` builds the options of the executed commands from the
+/// arguments.
+fn command_options(args: &cli::Cli) -> CommandOptions {
+    CommandOptions {
+        timeout: args.command_timeout,
+        shell: args.shell.clone(),
+    }
+}
+
+/// `build_file_index` searches the given paths for tagged files.
+fn build_file_index(args: &cli::Cli, roots: &[String], options: &SearchOptions) -> Vec<TaggedFile> {
+    let file_index = match get_tags_from_roots(roots, options) {
         Ok(index) => index,
         Err(e) => {
-            log_error("Failed to build file index:", e);
+            log_error("Failed to build file index:", &e);
+            std::process::exit(1);
+        }
+    };
+
+    report_warnings(&file_index.warnings, args);
+
+    file_index.files
+}
+
+/// `stream_files` starts searching the given paths for tagged files, which
+/// are returned while the search is still running. In strict and inspect mode,
+/// the search is completed and the warnings are reported first.
+fn stream_files(
+    args: &cli::Cli,
+    roots: &[String],
+    options: &SearchOptions,
+) -> Box<dyn Iterator<Item = Result<TaggedFile, IndexError>>> {
+    if args.strict || args.inspect {
+        return Box::new(build_file_index(args, roots, options).into_iter().map(Ok));
+    }
+
+    match TaggedFiles::walk_roots(roots, options) {
+        Ok(files) => Box::new(files),
+        Err(e) => {
+            log_error("Failed to build file index:", &e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `matches_query` checks if the tags of a file match the query.
+fn matches_query(file: &TaggedFile, query: &Pairs<Rule>) -> bool {
+    let ast = construct_query_ast(
+        query.clone().next().unwrap().into_inner(),
+        &file.tags.iter().map(std::string::String::as_str).collect(),
+    );
+
+    evaluate_ast(ast)
+}
+
+/// `CommandOutcome` is the result of executing the filter command and the
+/// command on a file that matched the query.
+enum CommandOutcome {
+    /// The filter command was unsuccessful or couldn't be executed.
+    Filtered(Option<tag::Error>),
+    /// The file passed the filter command. Contains the result of the
+    /// command if one was given.
+    Passed(Option<Result<CommandResult, tag::Error>>),
+}
+
+/// `run_commands` executes the filter command on a file and, if it passes,
+/// the command.
+fn run_commands(file: &TaggedFile, args: &cli::Cli) -> CommandOutcome {
+    let options = command_options(args);
+
+    if let Some(filter_command) = &args.filter_command {
+        match execute_filter_command_on_file(file, filter_command, &options) {
+            Ok(true) => (),
+            Ok(false) => return CommandOutcome::Filtered(None),
+            Err(e) => return CommandOutcome::Filtered(Some(e)),
+        }
+    }
+
+    if args.exec.is_empty() {
+        CommandOutcome::Passed(
+            args.command
+                .as_ref()
+                .map(|command| execute_command_on_file(file, command, &options)),
+        )
+    } else {
+        CommandOutcome::Passed(Some(execute_exec_on_file(file, &args.exec, &options)))
+    }
+}
+
+/// `MAX_BUFFERED_JOBS` is the maximum amount of files whose commands are
+/// started before the commands of an earlier file finished. It bounds the
+/// amount of output buffered while waiting for a slow command.
+const MAX_BUFFERED_JOBS: usize = 256;
+
+/// `CommandJobs` executes the commands on files that matched the query with
+/// up to `--jobs` jobs at once and returns the files in their original order.
+/// A new job starts as soon as one finishes, and finished jobs wait until the
+/// jobs of all earlier files finished. The output of each job is buffered, so
+/// the output of commands running at the same time never interleaves.
+struct CommandJobs<'a, I> {
+    files: I,
+    args: Arc<cli::Cli>,
+    pool: &'a rayon::ThreadPool,
+    sender: mpsc::Sender<(usize, TaggedFile, CommandOutcome)>,
+    receiver: mpsc::Receiver<(usize, TaggedFile, CommandOutcome)>,
+    /// The amount of jobs that were started.
+    started: usize,
+    /// The amount of jobs that were returned.
+    returned: usize,
+    /// The amount of jobs that are still running.
+    running: usize,
+    /// The finished jobs that wait for earlier jobs, by the index of their file.
+    done: BTreeMap<usize, (TaggedFile, CommandOutcome)>,
+}
+
+impl<'a, I: Iterator<Item = TaggedFile>> CommandJobs<'a, I> {
+    fn new(files: I, args: &cli::Cli, pool: &'a rayon::ThreadPool) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        Self {
+            files,
+            args: Arc::new(args.clone()),
+            pool,
+            sender,
+            receiver,
+            started: 0,
+            returned: 0,
+            running: 0,
+            done: BTreeMap::new(),
+        }
+    }
+
+    /// `start` starts jobs until `--jobs` jobs are running or the window of
+    /// buffered jobs is full.
+    fn start(&mut self) {
+        while self.running < self.pool.current_num_threads()
+            && self.started - self.returned < MAX_BUFFERED_JOBS
+        {
+            let Some(file) = self.files.next() else {
+                return;
+            };
+
+            let (index, args, sender) = (self.started, self.args.clone(), self.sender.clone());
+            self.pool.spawn(move || {
+                let outcome = run_commands(&file, &args);
+                // the receiver is gone if the jobs were stopped early
+                let _ = sender.send((index, file, outcome));
+            });
+
+            self.started += 1;
+            self.running += 1;
+        }
+    }
+}
+
+impl<I: Iterator<Item = TaggedFile>> Iterator for CommandJobs<'_, I> {
+    type Item = (TaggedFile, CommandOutcome);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.start();
+
+            if let Some(job) = self.done.remove(&self.returned) {
+                self.returned += 1;
+                return Some(job);
+            }
+
+            if self.running == 0 {
+                return None;
+            }
+
+            let (index, file, outcome) = self.receiver.recv().ok()?;
+            self.running -= 1;
+            self.done.insert(index, (file, outcome));
+        }
+    }
+}
+
+/// `fail_fast` exits if `--fail-fast` was given. It is called whenever a
+/// command failed.
+fn fail_fast(args: &cli::Cli) {
+    if args.fail_fast {
+        eprintln!(
+            "{} {}",
+            "[ERROR]".red().bold(),
+            "Stopping because a command failed.".red()
+        );
+        std::process::exit(1);
+    }
+}
+
+/// `report_filter_error` reports that the filter command couldn't be
+/// executed on a file.
+fn report_filter_error(file: &TaggedFile, e: &tag::Error, args: &cli::Cli) {
+    log_error(&format!("Failed to filter {}:", file.path.display()), e);
+    fail_fast(args);
+}
+
+/// `process_match` writes the tags of a matched file and prints it with the
+/// given prefix and the result of its command.
+fn process_match(
+    file: &TaggedFile,
+    args: &cli::Cli,
+    prefix: &str,
+    command_result: Option<&Result<CommandResult, tag::Error>>,
+) {
+    if args.write_xattr {
+        if let Err(e) = add_xattr_tags(&file.path, &file.own_tags()) {
+            log_error(
+                &format!("Failed to write tags of {}:", file.path.display()),
+                &e,
+            );
+        }
+    }
+
+    // the inspect mode shows the result of the command itself
+    if args.inspect {
+        return;
+    }
+
+    println!("{}", format!("{prefix}{}", file.path.display()).green());
+
+    if let Some(Err(e)) = command_result {
+        log_error(
+            &format!("Failed to execute command on {}:", file.path.display()),
+            e,
+        );
+    }
+
+    // don't print any more information in silent mode
+    if !args.silent {
+        non_interactive_output(file, command_result.and_then(|result| result.as_ref().ok()));
+    }
+
+    let failed = match command_result {
+        Some(Ok(result)) => !result.success(),
+        Some(Err(_)) => true,
+        None => false,
+    };
+    if failed {
+        fail_fast(args);
+    }
+}
+
+/// `build_job_pool` creates the thread pool executing commands on files.
+fn build_job_pool(args: &cli::Cli) -> rayon::ThreadPool {
+    match rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+    {
+        Ok(pool) => pool,
+        Err(e) => {
+            log_error("Failed to create thread pool:", &e);
             std::process::exit(1);
         }
+    }
+}
+
+/// `run_batch_command` executes the batch command once with the paths of
+/// all given files.
+fn run_batch_command(paths: &[PathBuf], args: &cli::Cli) {
+    let Some(command) = &args.command_batch else {
+        return;
     };
-    let query = match QueryParser::parse(Rule::tagsearch, query.as_str()) {
+
+    match execute_batch_command(paths, command, &command_options(args)) {
+        Ok(true) => (),
+        Ok(false) => {
+            eprintln!(
+                "{} {}",
+                "[ERROR]".red().bold(),
+                "The batch command exited unsuccessfully.".red()
+            );
+            fail_fast(args);
+        }
+        Err(e) => {
+            log_error("Failed to execute batch command:", &e);
+            fail_fast(args);
+        }
+    }
+}
+
+/// `watch` searches the changed parts of the paths again whenever files
+/// change and prints the files that started (`+`) or stopped (`-`) matching
+/// the query. The commands are only executed on files that started matching.
+/// Files that can't be indexed are reported, but don't stop watching in
+/// strict mode.
+fn watch(
+    args: &cli::Cli,
+    roots: &[String],
+    options: &SearchOptions,
+    query: &Pairs<Rule>,
+    pool: &rayon::ThreadPool,
+    mut matched: HashSet<PathBuf>,
+) -> ! {
+    let watcher = match IndexWatcher::new(roots, options) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            log_error("Failed to watch for changes:", &e);
+            std::process::exit(1);
+        }
+    };
+
+    loop {
+        let changes = match watcher.wait().and_then(|paths| Changes::new(roots, &paths)) {
+            Ok(changes) => changes,
+            Err(e) => {
+                log_error("Failed to watch for changes:", &e);
+                std::process::exit(1);
+            }
+        };
+        let files = match TaggedFiles::walk_changed(roots, &changes, options) {
+            Ok(files) => files,
+            Err(e) => {
+                log_error("Failed to build file index:", &e);
+                std::process::exit(1);
+            }
+        };
+
+        // the changed files are matched again, all others keep their state
+        let (previously_matched, unchanged) = matched
+            .into_iter()
+            .partition::<HashSet<_>, _>(|path| changes.contains(path));
+        matched = unchanged;
+
+        let mut newly_matched = Vec::new();
+        let mut warnings = Vec::new();
+        for file in files {
+            match file {
+                Ok(file) if !matches_query(&file, query) => (),
+                Ok(file) if previously_matched.contains(&file.path) => {
+                    matched.insert(file.path);
+                }
+                Ok(file) => newly_matched.push(file),
+                Err(warning) => warnings.push(warning),
+            }
+        }
+        print_warnings(&warnings, args);
+
+        let mut newly_matched_paths = Vec::new();
+        for (file, outcome) in CommandJobs::new(newly_matched.into_iter(), args, pool) {
+            let command_result = match outcome {
+                CommandOutcome::Passed(command_result) => command_result,
+                CommandOutcome::Filtered(error) => {
+                    if let Some(e) = error {
+                        report_filter_error(&file, &e, args);
+                    }
+                    continue;
+                }
+            };
+
+            process_match(&file, args, "+ ", command_result.as_ref());
+            newly_matched_paths.push(file.path.clone());
+            matched.insert(file.path);
+        }
+
+        run_batch_command(&newly_matched_paths, args);
+
+        let mut unmatched = previously_matched.difference(&matched).collect::<Vec<_>>();
+        unmatched.sort();
+        for path in unmatched {
+            println!("{}", format!("- {}", path.display()).red());
+        }
+    }
+}
+
+fn main() {
+    let mut args = cli::Cli::new_and_parse();
+
+    // detect if output is in a terminal or not
+    if !stdout().is_terminal() {
+        args.silent = true;
+        args.no_color = true;
+    }
+
+    if args.no_color {
+        colored::control::set_override(false);
+    }
+
+    let query = fetch_query(&args);
+    let query = match parse_query(&query) {
         Ok(query) => query,
         Err(e) => {
+            // the parser error shows where the query is invalid
             eprintln!(
                 "{} {}\n{}",
                 "[ERROR]".red().bold(),
                 "Invalid query: ".red(),
-                e.to_string().red()
+                e.source()
+                    .map_or_else(|| e.to_string(), ToString::to_string)
+                    .red()
             );
             std::process::exit(1);
         }
     };
 
+    // commands with a timeout don't receive Ctrl-C from the terminal
+    #[cfg(unix)]
+    if args.command_timeout.is_some() {
+        tag::commands::forward_signals();
+    }
+
+    let roots = fetch_roots(&args);
+    let options = search_options(&args);
+    let files = stream_files(&args, &roots, &options);
+    let pool = build_job_pool(&args);
+
     if args.inspect {
         if let Err(e) = enable_raw_mode() {
-            log_error("Failed to enable raw mode:", Box::new(e));
+            log_error("Failed to enable raw mode:", &e);
             std::process::exit(1);
         }
         if let Err(e) = stdout().execute(EnterAlternateScreen) {
-            log_error("Failed to enter alternate screen: ", Box::new(e));
+            log_error("Failed to enter alternate screen: ", &e);
         }
     }
 
     let mut file_matched_index = Vec::new();
-    let mut command_outputs = Vec::new();
-
-    for file in file_index {
-        let ast = construct_query_ast(
-            query.clone().next().unwrap().into_inner(),
-            &file.tags.iter().map(std::string::String::as_str).collect(),
-        );
-
-        // skip the file if tags don't match query
-        if !evaluate_ast(ast) {
-            continue;
-        }
-
-        // skip the file if filter command is unsuccessful
-        if args.filter_command.is_some()
-            && !execute_filter_command_on_file(&file.path, &args.filter_command.clone().unwrap())
-        {
-            continue;
+    let mut command_results = Vec::new();
+    let mut matched = HashSet::new();
+    let mut matched_paths = Vec::new();
+    let mut warnings = Vec::new();
+
+    let candidates = files.filter_map(|file| match file {
+        Ok(file) => matches_query(&file, &query).then_some(file),
+        Err(warning) => {
+            warnings.push(warning);
+            None
         }
-
-        if !args.inspect {
-            println!("{}", file.path.display().to_string().green());
-        }
-
-        let output = if args.command.is_some() {
-            execute_command_on_file(&file.path, &args.command.clone().unwrap())
-        } else {
-            String::new()
+    });
+
+    for (file, outcome) in CommandJobs::new(candidates, &args, &pool) {
+        // skip the file if the filter command is unsuccessful
+        let command_result = match outcome {
+            CommandOutcome::Passed(command_result) => command_result,
+            CommandOutcome::Filtered(error) => {
+                if let Some(e) = error {
+                    report_filter_error(&file, &e, &args);
+                }
+                continue;
+            }
         };
 
-        // don't print any more information in silent mode
-        if args.silent {
-            continue;
+        process_match(&file, &args, "", command_result.as_ref());
+        matched.insert(file.path.clone());
+        if args.command_batch.is_some() {
+            matched_paths.push(file.path.clone());
         }
 
-        if !args.inspect {
-            non_interactive_output(&file, output.as_str());
+        if args.inspect {
+            file_matched_index.push(file);
+            command_results.push(command_result);
         }
+    }
+
+    report_warnings(&warnings, &args);
+    run_batch_command(&matched_paths, &args);
 
-        file_matched_index.push(file);
-        command_outputs.push(output);
+    if args.watch {
+        watch(&args, &roots, &options, &query, &pool, matched);
     }
 
     if args.inspect {
-        if let Err(e) = inspect::interactive_output(&file_matched_index, &command_outputs) {
-            log_error("Failed to enter interactive output mode:", Box::new(e));
+        if let Err(e) = inspect::interactive_output(
+            &file_matched_index,
+            &command_results,
+            &command_options(&args),
+        ) {
+            log_error("Failed to enter interactive output mode:", &e);
             std::process::exit(1);
         }
 
         if let Err(e) = disable_raw_mode() {
-            log_error("Failed to disable raw mode:", Box::new(e));
+            log_error("Failed to disable raw mode:", &e);
         }
         if let Err(e) = stdout().execute(LeaveAlternateScreen) {
-            log_error("Failed to leave alternate screen:", Box::new(e));
+            log_error("Failed to leave alternate screen:", &e);
             std::process::exit(1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+    use tag::search::TaggedFile;
+
+    use super::{build_job_pool, cli, CommandJobs, CommandOutcome};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_jobs() {
+        struct TestCase<'a> {
+            name: &'a str,
+            jobs: &'a str,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_sequential",
+                jobs: "1",
+            },
+            TestCase {
+                name: "success_parallel",
+                jobs: "4",
+            },
+        ];
+
+        // later files finish first, so they have to wait for earlier ones
+        let names = (1..=8).rev().map(|i| i.to_string()).collect::<Vec<_>>();
+
+        for test_case in test_cases {
+            println!("test_command_jobs: \n\t{}", test_case.name);
+
+            let args = cli::Cli::parse_from([
+                "tag",
+                ".",
+                "#a",
+                "--shell",
+                "sh",
+                "-c",
+                "sleep 0.0$TAG_NAME; echo $TAG_NAME",
+                "-j",
+                test_case.jobs,
+            ]);
+            let files = names.iter().map(|name| TaggedFile {
+                path: PathBuf::from(name),
+                root: PathBuf::from("."),
+                tags: vec!["#a".to_string()],
+                inherited_tags: vec![],
+            });
+
+            let outputs = CommandJobs::new(files, &args, &build_job_pool(&args))
+                .map(|(file, outcome)| {
+                    let CommandOutcome::Passed(Some(Ok(result))) = outcome else {
+                        panic!("command failed on {}", file.path.display());
+                    };
+                    (file.path, result.stdout)
+                })
+                .collect::<Vec<_>>();
+
+            let expected_outputs = names
+                .iter()
+                .map(|name| (PathBuf::from(name), format!("{name}\n")))
+                .collect::<Vec<_>>();
+            assert_eq!(expected_outputs, outputs);
+        }
+    }
+}
@@ -9,18 +9,20 @@ pub mod onfile {
 }
 
 pub mod searchquery {
-    use pest::{iterators::Pairs, pratt_parser::PrattParser};
+    use std::sync::LazyLock;
+
+    use pest::{iterators::Pairs, pratt_parser::PrattParser, Parser};
     use pest_derive::Parser;
 
     /// Expr represents an AST for a search query.
     #[derive(Debug, PartialEq, Clone)]
     pub enum Expr {
         Bool(bool),
-        UnaryNot(Box<Expr>),
+        UnaryNot(Box<Self>),
         Operation {
-            lhs: Box<Expr>,
+            lhs: Box<Self>,
             op: Op,
-            rhs: Box<Expr>,
+            rhs: Box<Self>,
         },
     }
 
@@ -31,17 +33,15 @@ pub mod searchquery {
         Or,
     }
 
-    lazy_static::lazy_static! {
-        static ref PRATT_PARSER: PrattParser<Rule> = {
-            use pest::pratt_parser::{Assoc::Left, Op};
-            use Rule::{and, or, unary_not};
+    static PRATT_PARSER: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+        use pest::pratt_parser::{Assoc::Left, Op};
+        use Rule::{and, or, unary_not};
 
-            PrattParser::new()
-                // & and | are evaluated with the same precedence
-                .op(Op::infix(and, Left) | Op::infix(or, Left))
-                .op(Op::prefix(unary_not))
-        };
-    }
+        PrattParser::new()
+            // & and | are evaluated with the same precedence
+            .op(Op::infix(and, Left) | Op::infix(or, Left))
+            .op(Op::prefix(unary_not))
+    });
 
     #[derive(Parser)]
     #[grammar = "query.pest"]
@@ -49,6 +49,15 @@ pub mod searchquery {
     /// The relevant rule is `tagsearch`.
     pub struct QueryParser;
 
+    /// `parse_query()` parses a search query with the `QueryParser`.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if the query is invalid.
+    pub fn parse_query(query: &str) -> Result<Pairs<'_, Rule>, crate::Error> {
+        Ok(QueryParser::parse(Rule::tagsearch, query)?)
+    }
+
     /// `construct_query_ast()` creates an AST from a string of symbols
     /// lexed by the `QueryParser` and a list of tags.
     #[must_use]
@@ -133,6 +142,12 @@ mod tests {
                 expected_tags: vec!["#1", "#asdf", "#something-idk"],
                 expected_error: false,
             },
+            TestCase {
+                name: "success_multi_line",
+                input: "tags: [#1\n  #2\n  #3\n]",
+                expected_tags: vec!["#1", "#2", "#3"],
+                expected_error: false,
+            },
             TestCase {
                 name: "fail_no_brackets",
                 input: "tags:#1#2#3",
@@ -151,6 +166,12 @@ mod tests {
                 expected_tags: vec![],
                 expected_error: true,
             },
+            TestCase {
+                name: "fail_tag_across_lines",
+                input: "tags: [#foo\nbar #baz]",
+                expected_tags: vec![],
+                expected_error: true,
+            },
         ];
 
         for test_case in test_cases {
@@ -159,14 +180,14 @@ mod tests {
             let res = onfile::TaglineParser::parse(onfile::Rule::tagline, test_case.input);
             if res.is_err() {
                 assert!(test_case.expected_error);
-                return;
+                continue;
             }
 
             assert!(!test_case.expected_error);
 
             for (i, tag) in res.unwrap().enumerate() {
                 if tag.as_rule() == onfile::Rule::tag {
-                    assert_eq!(tag.as_str().trim(), test_case.expected_tags[i]);
+                    assert_eq!(tag.as_str(), test_case.expected_tags[i]);
                 }
             }
         }
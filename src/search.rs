@@ -1,67 +1,2087 @@
 use std::{
-    fs,
-    io::{BufRead, BufReader},
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    io::{self, Seek},
     path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
 };
 
+use ignore::{overrides::OverrideBuilder, types::TypesBuilder, WalkBuilder};
 use pest::Parser;
-use walkdir::WalkDir;
+use rayon::prelude::*;
 
+use crate::cache::{CacheEntry, FileStamp, IndexCache};
+use crate::error::Error;
 use crate::parsers::onfile::{self, TaglineParser};
+use crate::sources::{
+    get_tags_from_tagline, read_tagline, read_text, text_reader, DecodeError, InlineTagSource,
+    NativeFormatSource, TagSource, TaglineSource, XattrSource,
+};
 
 /// `TaggedFile` is a file that contains tags.
 #[derive(Clone, Debug)]
 pub struct TaggedFile {
     pub path: PathBuf,
+    /// The searched directory the file was found in.
+    pub root: PathBuf,
+    /// All tags of the file, including the inherited ones.
     pub tags: Vec<String>,
+    /// The tags inherited from `.tagdir` files of parent directories.
+    pub inherited_tags: Vec<String>,
+}
+
+impl TaggedFile {
+    /// `own_tags()` returns the tags of the file that weren't inherited.
+    #[must_use]
+    pub fn own_tags(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter(|tag| !self.inherited_tags.contains(tag))
+            .cloned()
+            .collect()
+    }
+}
+
+/// `WarningReason` is the reason a file couldn't be indexed.
+#[derive(Debug)]
+pub enum WarningReason {
+    /// The file or directory couldn't be read.
+    Io(io::Error),
+    /// The content of the file isn't text.
+    Decode,
+    /// The file contains a tagline that can't be parsed.
+    MalformedTagline(String),
+    /// The directory is a symbolic link to one of its own ancestors.
+    SymlinkLoop(PathBuf),
+    /// A tag source failed for another reason.
+    Other(String),
+}
+
+impl WarningReason {
+    /// `from_error()` determines the reason of an error returned while
+    /// reading the tags of a file.
+    fn from_error(e: Error) -> Self {
+        match e {
+            Error::Io(e) | Error::File { source: e, .. } => Self::Io(e),
+            Error::Walk(e) => {
+                if let Some(ancestor) = get_loop_ancestor(&e) {
+                    return Self::SymlinkLoop(ancestor.to_owned());
+                }
+                let message = e.to_string();
+                e.into_io_error().map_or(Self::Other(message), Self::Io)
+            }
+            Error::Decode(_) => Self::Decode,
+            Error::Tagline(e) => Self::MalformedTagline(e.to_string()),
+            e => Self::Other(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for WarningReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read: {e}"),
+            Self::Decode => write!(f, "{DecodeError}"),
+            Self::MalformedTagline(e) => write!(f, "malformed tagline:\n{e}"),
+            Self::SymlinkLoop(ancestor) => {
+                write!(f, "symbolic link loop to {}", ancestor.display())
+            }
+            Self::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// `Warning` describes a file that was skipped or couldn't be fully indexed.
+#[derive(Debug)]
+pub struct Warning {
+    pub path: PathBuf,
+    pub reason: WarningReason,
+}
+
+impl Warning {
+    /// `new()` creates a warning for a file from the error that occurred.
+    fn new(path: &Path, e: Error) -> Self {
+        Self {
+            path: path.to_owned(),
+            reason: WarningReason::from_error(e),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.reason)
+    }
+}
+
+/// `IndexError` is an error that occurred while indexing a single file.
+/// The file is skipped or only partially indexed, but the search continues.
+pub type IndexError = Warning;
+
+/// `FileIndex` contains the tagged files found in a directory and the
+/// warnings for files that couldn't be indexed.
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    pub files: Vec<TaggedFile>,
+    pub warnings: Vec<Warning>,
+}
+
+impl FileIndex {
+    /// `warn()` adds a warning for a file.
+    fn warn(&mut self, path: &Path, e: Error) {
+        self.warnings.push(Warning::new(path, e));
+    }
+}
+
+/// `SearchOptions` configures how files are searched for tags.
+#[derive(Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct SearchOptions {
+    /// Also collect `#hashtags` found anywhere in the body of a file.
+    pub inline_tags: bool,
+    /// Also collect tags stored in the `user.xdg.tags` extended attribute.
+    pub xattr_tags: bool,
+    /// The chain of tag sources that replaces the built-in sources chosen by
+    /// `inline_tags` and `xattr_tags`. Sources run in the given order, so
+    /// built-in sources can also be removed or reordered. The cache is
+    /// disabled with custom sources, since it can't tell what they read.
+    pub sources: Option<Vec<Arc<dyn TagSource>>>,
+    /// The amount of threads reading files in parallel. `0` uses one
+    /// thread per CPU.
+    pub threads: usize,
+    /// Don't skip files matched by `.gitignore`, `.ignore` and `.tagignore` files.
+    pub no_ignore: bool,
+    /// Don't skip hidden files and directories.
+    pub hidden: bool,
+    /// Only search files matching one of these globs.
+    pub include: Vec<String>,
+    /// Skip files and directories matching one of these globs.
+    pub exclude: Vec<String>,
+    /// Only search files of these types, e.g. `md` or `txt`.
+    /// The available types are the same as in `ripgrep`.
+    pub types: Vec<String>,
+    /// Don't descend into directories deeper than this.
+    pub max_depth: Option<usize>,
+    /// Skip files less deep than this.
+    pub min_depth: Option<usize>,
+    /// Skip files larger than this amount of bytes.
+    pub max_filesize: Option<u64>,
+    /// Don't follow symbolic links.
+    pub no_follow: bool,
+    /// The directory the tags of searched files are cached in, so that only
+    /// files that changed since the last search need to be read. The cache
+    /// isn't used if `sources` is set.
+    pub cache: Option<PathBuf>,
+    /// Ignore the existing cache and read all files again.
+    pub rebuild_cache: bool,
+}
+
+impl SearchOptions {
+    /// `tag_sources()` returns the chain of tag sources configured by the options.
+    #[must_use]
+    pub fn tag_sources(&self) -> Vec<Arc<dyn TagSource>> {
+        self.sources
+            .clone()
+            .unwrap_or_else(|| self.default_tag_sources())
+    }
+
+    /// `default_tag_sources()` returns the chain of built-in tag sources
+    /// chosen by `inline_tags` and `xattr_tags`, which is used unless
+    /// `sources` is set. It's a starting point for custom chains.
+    #[must_use]
+    pub fn default_tag_sources(&self) -> Vec<Arc<dyn TagSource>> {
+        let mut sources: Vec<Arc<dyn TagSource>> =
+            vec![Arc::new(TaglineSource), Arc::new(NativeFormatSource)];
+
+        if self.inline_tags {
+            sources.push(Arc::new(InlineTagSource));
+        }
+
+        if self.xattr_tags {
+            sources.push(Arc::new(XattrSource));
+        }
+
+        sources
+    }
+}
+
+/// `SIDECAR_EXTENSION` is the extension of sidecar files that contain
+/// the tagline of the file they are named after.
+const SIDECAR_EXTENSION: &str = "tags";
+
+/// `TAGDIR_NAME` is the name of a file containing a tagline whose tags are
+/// inherited by all files beneath its directory.
+const TAGDIR_NAME: &str = ".tagdir";
+
+/// `MANIFEST_NAME` is the name of a per-directory manifest that maps
+/// the names of files in its directory to tags.
+const MANIFEST_NAME: &str = ".tags";
+
+/// `extend_tags()` adds all tags of `new_tags` to `tags` that aren't
+/// already contained in it.
+fn extend_tags(tags: &mut Vec<String>, new_tags: Vec<String>) {
+    for tag in new_tags {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
 }
 
-/// `get_tags_from_file()` returns a list of tags found in a file.
-/// It will return an error if a file has no parsable tags.
-fn get_tags_from_file(file: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let file = fs::File::open(file)?;
-    let mut buffer = BufReader::new(file);
-    let mut tagline = String::new();
-    let _ = buffer.read_line(&mut tagline)?;
+/// `get_tags_from_file()` returns the tags found in a file by a chain of
+/// tag sources. Every source reads the file from its start.
+/// Failing sources are reported as warnings and don't prevent the other
+/// sources from finding tags.
+fn get_tags_from_file(
+    path: &Path,
+    sources: &[Arc<dyn TagSource>],
+    index: &mut FileIndex,
+) -> Option<Vec<String>> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            index.warn(path, e.into());
+            return None;
+        }
+    };
+    let mut tags: Option<Vec<String>> = None;
+
+    for source in sources {
+        if let Err(e) = file.rewind() {
+            index.warn(path, e.into());
+            break;
+        }
+
+        match source.get_tags(path, &mut text_reader(&file)) {
+            Ok(Some(source_tags)) => extend_tags(tags.get_or_insert_with(Vec::new), source_tags),
+            Ok(None) => {}
+            Err(e) => index.warn(path, e),
+        }
+    }
+
+    tags
+}
+
+/// `get_sidecar_path()` returns the path of the sidecar file of a file.
+fn get_sidecar_path(file: &Path) -> PathBuf {
+    let mut sidecar = file.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(SIDECAR_EXTENSION);
+    PathBuf::from(sidecar)
+}
+
+/// `get_tags_from_sidecar()` returns the tags of the tagline in a sidecar
+/// or `.tagdir` file.
+/// It will return an error if the sidecar file has no parsable tagline.
+fn get_tags_from_sidecar(sidecar: &Path) -> Result<Vec<String>, Error> {
+    let file = fs::File::open(sidecar).map_err(Error::file(sidecar))?;
+    let tagline = read_tagline(&mut text_reader(file))?;
+
+    get_tags_from_tagline(&tagline)
+}
+
+/// `parse_manifest()` returns the files listed in a manifest located
+/// in `directory`. Each non-empty line of a manifest has the form
+/// `filename: [#tag1 #tag2]`.
+/// It will return an error if a line can't be parsed.
+fn parse_manifest(directory: &Path, content: &str) -> Result<Vec<(PathBuf, Vec<String>)>, Error> {
+    let mut tagged_files = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, taglist) = line
+            .rsplit_once(':')
+            .ok_or_else(|| Error::Manifest(line.to_string()))?;
+        let parsed = TaglineParser::parse(onfile::Rule::manifest_tags, taglist.trim())?;
+
+        tagged_files.push((
+            directory.join(name.trim()),
+            parsed
+                .filter(|tag| tag.as_rule() == onfile::Rule::tag)
+                .map(|tag| tag.as_str().to_string())
+                .collect(),
+        ));
+    }
+
+    Ok(tagged_files)
+}
+
+/// `get_tags_from_manifest()` returns the files listed in a manifest.
+/// It will return an error if the manifest can't be read or parsed.
+fn get_tags_from_manifest(manifest: &Path) -> Result<Vec<(PathBuf, Vec<String>)>, Error> {
+    let directory = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let file = fs::File::open(manifest).map_err(Error::file(manifest))?;
+    let content = read_text(&mut text_reader(file))?;
+
+    parse_manifest(directory, &content)
+}
+
+/// `IndexJob` is a file found while walking a directory whose tags
+/// still need to be read.
+struct IndexJob {
+    path: PathBuf,
+    /// The index of the searched directory the file was found in.
+    root: usize,
+    root_directory: PathBuf,
+    /// Whether the file was reached through a symbolic link.
+    linked: bool,
+    /// The sidecar file of the file if it has one.
+    sidecar: Option<PathBuf>,
+    /// The tags of the file listed in the manifest of its directory.
+    manifest_tags: Vec<String>,
+    /// The tags inherited from `.tagdir` files.
+    inherited_tags: Vec<String>,
+}
+
+impl IndexJob {
+    /// `canonical_path()` returns the canonical path of the job's file.
+    /// Only files reached through symbolic links need to be resolved, all
+    /// other files are located relative to the canonical path of their root.
+    fn canonical_path(&self, canonical_root: &Path) -> PathBuf {
+        match self.path.strip_prefix(&self.root_directory) {
+            Ok(relative) if !self.linked => canonical_root.join(relative),
+            _ => fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone()),
+        }
+    }
+
+    /// `run()` reads the tags of the job's file and its sidecar file.
+    /// The tags of the file itself are taken from the cache if it didn't
+    /// change, otherwise the new cache entry of the file is returned.
+    fn run(
+        self,
+        sources: &[Arc<dyn TagSource>],
+        cache: Option<&IndexCache>,
+    ) -> (FileIndex, Option<CacheEntry>) {
+        let mut index = FileIndex::default();
+        let (mut tags, cache_entry) = self.read_tags(sources, cache, &mut index);
+
+        if let Some(sidecar) = self.sidecar {
+            match get_tags_from_sidecar(&sidecar) {
+                Ok(sidecar_tags) => extend_tags(tags.get_or_insert_with(Vec::new), sidecar_tags),
+                Err(e) => index.warn(&sidecar, e),
+            }
+        }
+
+        if !self.manifest_tags.is_empty() {
+            extend_tags(tags.get_or_insert_with(Vec::new), self.manifest_tags);
+        }
+
+        if tags.is_none() && self.inherited_tags.is_empty() {
+            return (index, cache_entry);
+        }
+
+        let mut tags = tags.unwrap_or_default();
+        let inherited_tags = self
+            .inherited_tags
+            .into_iter()
+            .filter(|tag| !tags.contains(tag))
+            .collect::<Vec<_>>();
+        tags.extend(inherited_tags.iter().cloned());
+
+        index.files.push(TaggedFile {
+            path: self.path,
+            root: self.root_directory,
+            tags,
+            inherited_tags,
+        });
+
+        (index, cache_entry)
+    }
+
+    /// `read_tags()` reads the tags of the file itself using the cache.
+    /// Files that couldn't be read without warnings aren't cached.
+    fn read_tags(
+        &self,
+        sources: &[Arc<dyn TagSource>],
+        cache: Option<&IndexCache>,
+        index: &mut FileIndex,
+    ) -> (Option<Vec<String>>, Option<CacheEntry>) {
+        let Some((cache, stamp)) =
+            cache.and_then(|cache| Some((cache, FileStamp::new(&self.path).ok()?)))
+        else {
+            return (get_tags_from_file(&self.path, sources, index), None);
+        };
+
+        if let Some(tags) = cache.get(&self.path, &stamp) {
+            let tags = tags.clone();
+            return (tags.clone(), Some(CacheEntry { stamp, tags }));
+        }
+
+        let warnings = index.warnings.len();
+        let tags = get_tags_from_file(&self.path, sources, index);
+        let cache_entry = (index.warnings.len() == warnings).then(|| CacheEntry {
+            stamp,
+            tags: tags.clone(),
+        });
+
+        (tags, cache_entry)
+    }
+}
 
-    let parsed = TaglineParser::parse(onfile::Rule::tagline, tagline.trim())?;
+/// `IGNORE_NAME` is the name of project-specific ignore files that are
+/// respected in addition to `.gitignore` and `.ignore` files.
+const IGNORE_NAME: &str = ".tagignore";
 
+/// `get_walk_error_path()` returns the path a walk error occurred at.
+fn get_walk_error_path(e: &ignore::Error) -> Option<&Path> {
+    match e {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            get_walk_error_path(err)
+        }
+        ignore::Error::Partial(errs) => errs.iter().find_map(get_walk_error_path),
+        _ => None,
+    }
+}
+
+/// `get_loop_ancestor()` returns the directory a symbolic link loop
+/// leads back to if the walk error is caused by a loop.
+fn get_loop_ancestor(e: &ignore::Error) -> Option<&Path> {
+    match e {
+        ignore::Error::Loop { ancestor, .. } => Some(ancestor),
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => get_loop_ancestor(err),
+        _ => None,
+    }
+}
+
+/// `build_walker()` creates a walker for a directory that applies the
+/// ignore files and filters configured in the options.
+fn build_walker(directory: &str, options: &SearchOptions) -> Result<WalkBuilder, Error> {
+    let mut walker = WalkBuilder::new(directory);
+    walker
+        .follow_links(!options.no_follow)
+        .sort_by_file_name(Ord::cmp)
+        .standard_filters(!options.no_ignore)
+        .hidden(!options.hidden)
+        .max_depth(options.max_depth)
+        .max_filesize(options.max_filesize);
+
+    if !options.no_ignore {
+        walker.add_custom_ignore_filename(IGNORE_NAME);
+    }
+
+    if !options.include.is_empty() || !options.exclude.is_empty() {
+        let mut overrides = OverrideBuilder::new(directory);
+        for glob in &options.include {
+            overrides.add(glob).map_err(Error::Filter)?;
+        }
+        for glob in &options.exclude {
+            overrides.add(&format!("!{glob}")).map_err(Error::Filter)?;
+        }
+        walker.overrides(overrides.build().map_err(Error::Filter)?);
+    }
+
+    if !options.types.is_empty() {
+        let mut types = TypesBuilder::new();
+        types.add_defaults();
+        for file_type in &options.types {
+            types.select(file_type);
+        }
+        walker.types(types.build().map_err(Error::Filter)?);
+    }
+
+    Ok(walker)
+}
+
+/// `is_ignore_file()` checks if a file name is the name of an ignore file
+/// that the walker reads.
+fn is_ignore_file(name: &OsStr) -> bool {
+    name == ".gitignore" || name == ".ignore" || name == IGNORE_NAME
+}
+
+/// `get_deciding_paths()` returns the paths that have to be searched for a
+/// changed path to affect the search. Files that only change the tags of
+/// other files matter if their directory or the other file is searched.
+fn get_deciding_paths(path: &Path) -> Vec<PathBuf> {
+    let parent = path.parent().map(Path::to_path_buf);
+    let Some(name) = path.file_name() else {
+        return vec![path.to_path_buf()];
+    };
+
+    if name == TAGDIR_NAME || name == MANIFEST_NAME || is_ignore_file(name) {
+        return parent.into_iter().collect();
+    }
+
+    let mut paths = vec![path.to_path_buf()];
+    if path
+        .extension()
+        .is_some_and(|extension| extension == SIDECAR_EXTENSION)
+    {
+        paths.push(path.with_extension(""));
+    }
+    // deleted paths matter if their directory is searched
+    if fs::symlink_metadata(path).is_err() {
+        paths.extend(parent);
+    }
+    paths
+}
+
+/// `filter_ignored()` returns the changed paths beneath the canonical roots
+/// that can affect a search, skipping paths like the contents of `.git/` or
+/// ignored build directories. The roots are only walked along the changed
+/// paths, so that exactly the ignore files and filters of a search apply.
+pub(crate) fn filter_ignored(
+    roots: &[PathBuf],
+    paths: Vec<PathBuf>,
+    options: &SearchOptions,
+) -> Vec<PathBuf> {
+    let mut searched = HashSet::new();
+
+    for root in roots {
+        let deciding = paths
+            .iter()
+            .filter(|path| path.starts_with(root))
+            .flat_map(|path| get_deciding_paths(path))
+            .collect::<Vec<_>>();
+        if deciding.is_empty() {
+            continue;
+        }
+
+        // the directories leading to the deciding paths are walked, too
+        let wanted = deciding
+            .iter()
+            .flat_map(|path| path.ancestors())
+            .filter(|path| path.starts_with(root))
+            .map(Path::to_path_buf)
+            .collect::<HashSet<_>>();
+        let Some(Ok(mut walker)) = root.to_str().map(|root| build_walker(root, options)) else {
+            // without a valid walker, the change can't be ruled out
+            searched.extend(deciding);
+            continue;
+        };
+        let wanted = Arc::new(wanted);
+        walker.filter_entry({
+            let wanted = Arc::clone(&wanted);
+            move |entry| wanted.contains(entry.path())
+        });
+
+        searched.extend(
+            walker
+                .build()
+                .filter_map(Result::ok)
+                .map(ignore::DirEntry::into_path),
+        );
+    }
+
+    paths
+        .into_iter()
+        .filter(|path| {
+            get_deciding_paths(path)
+                .iter()
+                .any(|path| searched.contains(path))
+        })
+        .collect()
+}
+
+/// `Scope` is a part of a searched directory whose files have to be read
+/// again after a file changed.
+#[derive(Clone, Debug)]
+enum Scope {
+    /// A file or a directory with everything beneath it.
+    Subtree(PathBuf),
+    /// The files directly inside a directory.
+    Children(PathBuf),
+}
+
+impl Scope {
+    /// `from_changed_path()` returns the scopes affected by a changed path.
+    /// A `.tagdir` file or an ignore file affects everything beneath its
+    /// directory, a manifest affects the files next to it and a sidecar
+    /// file affects the file it belongs to.
+    fn from_changed_path(path: PathBuf) -> Vec<Self> {
+        let Some(name) = path.file_name() else {
+            return vec![Self::Subtree(path)];
+        };
+
+        let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        if name == TAGDIR_NAME || is_ignore_file(name) {
+            return vec![Self::Subtree(parent)];
+        }
+        if name == MANIFEST_NAME {
+            return vec![Self::Children(parent)];
+        }
+        if path
+            .extension()
+            .is_some_and(|extension| extension == SIDECAR_EXTENSION)
+        {
+            return vec![Self::Subtree(path.with_extension("")), Self::Subtree(path)];
+        }
+        vec![Self::Subtree(path)]
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Self::Subtree(path) | Self::Children(path) => path,
+        }
+    }
+
+    /// `contains()` checks if a path found by a search is part of the scope.
+    fn contains(&self, path: &Path) -> bool {
+        match self {
+            Self::Subtree(subtree) => path.starts_with(subtree),
+            Self::Children(directory) => path.parent() == Some(directory.as_path()),
+        }
+    }
+
+    /// `leads_to()` checks if a search has to descend into a path to reach
+    /// the scope.
+    fn leads_to(&self, path: &Path) -> bool {
+        self.path().starts_with(path) || self.contains(path)
+    }
+}
+
+/// `Changes` are the parts of the searched directories whose files have to
+/// be read again after files changed, like everything beneath a directory
+/// whose `.tagdir` file changed.
+#[derive(Clone, Debug)]
+pub struct Changes {
+    /// The scopes of each searched directory in the form the search
+    /// returns paths in.
+    scopes: Vec<Vec<Scope>>,
+}
+
+impl Changes {
+    /// `new()` determines the parts of the searched directories that are
+    /// affected by the changed canonical paths, like the paths returned by
+    /// `IndexWatcher::wait()`.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if one of the given directories can't be read.
+    pub fn new(directories: &[impl AsRef<str>], changed: &[PathBuf]) -> Result<Self, Error> {
+        let mut scopes = Vec::new();
+        for directory in directories {
+            let directory = directory.as_ref();
+            let root = fs::canonicalize(directory).map_err(Error::file(Path::new(directory)))?;
+            scopes.push(
+                changed
+                    .iter()
+                    .filter_map(|path| path.strip_prefix(&root).ok())
+                    .flat_map(|path| Scope::from_changed_path(Path::new(directory).join(path)))
+                    .collect(),
+            );
+        }
+
+        Ok(Self { scopes })
+    }
+
+    /// `contains()` checks if a file returned by a search of the directories
+    /// is affected by the changes.
+    #[must_use]
+    pub fn contains(&self, path: &Path) -> bool {
+        self.scopes
+            .iter()
+            .flatten()
+            .any(|scope| scope.contains(path))
+    }
+}
+
+/// `WalkedDirectory` is a directory that is currently being walked.
+struct WalkedDirectory {
+    depth: usize,
+    /// The tags of the directory's `.tagdir` file.
+    tags: Vec<String>,
+    /// The tags of the files listed in the directory's manifest.
+    manifest: HashMap<PathBuf, Vec<String>>,
+    /// The names of all files in the directory, which are used to find
+    /// sidecar files without looking each of them up.
+    file_names: HashSet<OsString>,
+}
+
+impl WalkedDirectory {
+    /// `read()` lists a directory and reads its `.tagdir` file and manifest.
+    /// Errors listing the directory are ignored, because the walk reports
+    /// them. Returns `None` if the indexer stopped.
+    fn read(path: &Path, depth: usize, indexer: &mut Indexer) -> Option<Self> {
+        // the parent of a relative file name is empty
+        let listed = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        let file_names: HashSet<OsString> = fs::read_dir(listed)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter(|entry| !entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+                    .map(|entry| entry.file_name())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut directory = Self {
+            depth,
+            tags: Vec::new(),
+            manifest: HashMap::new(),
+            file_names,
+        };
+
+        // both files are hidden, so they are looked up in the listing
+        if directory.file_names.contains(OsStr::new(TAGDIR_NAME)) {
+            let tagdir = path.join(TAGDIR_NAME);
+            match get_tags_from_sidecar(&tagdir) {
+                Ok(tags) => directory.tags = tags,
+                Err(e) => {
+                    if !indexer.warn(Warning::new(&tagdir, e)) {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        if directory.file_names.contains(OsStr::new(MANIFEST_NAME)) {
+            let manifest = path.join(MANIFEST_NAME);
+            match get_tags_from_manifest(&manifest) {
+                Ok(files) => directory.manifest = files.into_iter().collect(),
+                Err(e) => {
+                    if !indexer.warn(Warning::new(&manifest, e)) {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        Some(directory)
+    }
+
+    /// `is_sidecar()` returns whether a file of the directory is the sidecar
+    /// file of another file.
+    fn is_sidecar(&self, name: &OsStr) -> bool {
+        let name = Path::new(name);
+        name.extension()
+            .is_some_and(|extension| extension == SIDECAR_EXTENSION)
+            && name
+                .file_stem()
+                .is_some_and(|target| self.file_names.contains(target))
+    }
+
+    /// `get_sidecar()` returns the path of the sidecar file of a file of the
+    /// directory if it exists.
+    fn get_sidecar(&self, file: &Path) -> Option<PathBuf> {
+        let sidecar = get_sidecar_path(file);
+        sidecar
+            .file_name()
+            .is_some_and(|name| self.file_names.contains(name))
+            .then_some(sidecar)
+    }
+}
+
+/// `get_ancestor_tags()` returns the tags of the `.tagdir` files in the
+/// parent directories of a searched directory, which are inherited by all
+/// of its files. Returns `None` if the indexer stopped.
+fn get_ancestor_tags(canonical_root: &Path, indexer: &mut Indexer) -> Option<Vec<String>> {
     let mut tags = Vec::new();
 
-    for tag in parsed {
-        if tag.as_rule() == onfile::Rule::tag {
-            tags.push(tag.as_str().to_string());
+    let ancestors = canonical_root.ancestors().skip(1).collect::<Vec<_>>();
+    for ancestor in ancestors.into_iter().rev() {
+        let tagdir = ancestor.join(TAGDIR_NAME);
+        if !tagdir.is_file() {
+            continue;
+        }
+
+        match get_tags_from_sidecar(&tagdir) {
+            Ok(tagdir_tags) => extend_tags(&mut tags, tagdir_tags),
+            Err(e) => {
+                if !indexer.warn(Warning::new(&tagdir, e)) {
+                    return None;
+                }
+            }
         }
     }
 
-    Ok(tags)
+    Some(tags)
+}
+
+/// `walk_directory()` walks a directory and passes the files whose tags
+/// need to be read to the indexer, sorted by their path. Directories are read
+/// in order to determine the tags inherited from `.tagdir` files and listed
+/// in manifests. Ignore files and hidden files are respected like in
+/// `ripgrep` unless disabled. Files reached through symbolic links are
+/// passed after all other files if files are deduplicated, so that a file is
+/// listed under its own path instead of a link to it. Returns `false` if the
+/// indexer stopped.
+fn walk_directory(
+    directory: &str,
+    root: usize,
+    walker: &WalkBuilder,
+    options: &SearchOptions,
+    indexer: &mut Indexer,
+) -> bool {
+    let Some(ancestor_tags) = get_ancestor_tags(&indexer.roots[root].clone(), indexer) else {
+        return false;
+    };
+    // the directories from the root to the current entry
+    let mut directories: Vec<WalkedDirectory> = Vec::new();
+    // the depths of followed symbolic links to directories
+    let mut links: Vec<usize> = Vec::new();
+    // the files reached through symbolic links
+    let mut linked_jobs = Vec::new();
+
+    for entry in walker.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = get_walk_error_path(&e)
+                    .unwrap_or_else(|| Path::new(directory))
+                    .to_owned();
+                if !indexer.warn(Warning::new(&path, e.into())) {
+                    return false;
+                }
+                continue;
+            }
+        };
+
+        if let Some(e) = entry.error() {
+            if !indexer.warn(Warning::new(entry.path(), e.clone().into())) {
+                return false;
+            }
+        }
+
+        // forget the directories that were left
+        directories.retain(|directory| directory.depth < entry.depth());
+        links.retain(|depth| *depth < entry.depth());
+
+        if entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_dir())
+        {
+            // a linked root is resolved by the canonical path of the root
+            if entry.depth() > 0 && entry.path_is_symlink() {
+                links.push(entry.depth());
+            }
+
+            match WalkedDirectory::read(entry.path(), entry.depth(), indexer) {
+                Some(directory) => directories.push(directory),
+                None => return false,
+            }
+            continue;
+        }
+
+        // skips unfollowed symbolic links and special files like pipes
+        if entry.depth() < options.min_depth.unwrap_or(0)
+            || !entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        {
+            continue;
+        }
+
+        let linked = entry.path_is_symlink() || !links.is_empty();
+        let path = entry.into_path();
+        let mut root_parent = None;
+        let parent = if let Some(parent) = directories.last_mut() {
+            parent
+        } else {
+            // a file given as the root is looked up in its own directory,
+            // whose `.tagdir` tags are already inherited as ancestor tags
+            let directory = path.parent().unwrap_or_else(|| Path::new(""));
+            match WalkedDirectory::read(directory, 0, indexer) {
+                Some(directory) => root_parent.insert(directory),
+                None => return false,
+            }
+        };
+
+        if path.file_name().is_some_and(|name| {
+            name == TAGDIR_NAME || name == MANIFEST_NAME || parent.is_sidecar(name)
+        }) {
+            continue;
+        }
+
+        let sidecar = parent.get_sidecar(&path);
+        let manifest_tags = parent.manifest.remove(&path).unwrap_or_default();
+
+        let mut inherited_tags = ancestor_tags.clone();
+        extend_tags(
+            &mut inherited_tags,
+            directories
+                .iter()
+                .flat_map(|directory| &directory.tags)
+                .cloned()
+                .collect(),
+        );
+
+        let job = IndexJob {
+            root,
+            root_directory: PathBuf::from(directory),
+            linked,
+            sidecar,
+            manifest_tags,
+            path,
+            inherited_tags,
+        };
+        if linked && indexer.deduplicate {
+            linked_jobs.push(job);
+        } else if !indexer.push(job) {
+            return false;
+        }
+    }
+
+    linked_jobs.into_iter().all(|job| indexer.push(job))
+}
+
+/// `BATCH_SIZE` is the amount of files whose tags are read in parallel
+/// before they are yielded by `TaggedFiles`.
+const BATCH_SIZE: usize = 256;
+
+/// `Indexer` reads the tags of the files found while walking in batches
+/// and sends the results to `TaggedFiles`.
+struct Indexer {
+    pool: rayon::ThreadPool,
+    sources: Vec<Arc<dyn TagSource>>,
+    /// The canonical paths of the searched directories.
+    roots: Vec<PathBuf>,
+    /// The caches of the searched directories.
+    caches: Vec<Option<IndexCache>>,
+    /// Whether files can be found through multiple paths.
+    deduplicate: bool,
+    /// The canonical paths of the files found so far.
+    seen_files: HashSet<PathBuf>,
+    seen_warnings: HashSet<(PathBuf, String)>,
+    batch: Vec<IndexJob>,
+    sender: mpsc::SyncSender<Result<TaggedFile, IndexError>>,
+}
+
+impl Indexer {
+    /// `warn()` sends a warning unless it was already sent, e.g. because of
+    /// overlapping directories or for a symbolic link loop that was reached
+    /// through different paths. Returns `false` if the receiver is gone.
+    fn warn(&mut self, warning: Warning) -> bool {
+        let path = match warning.reason {
+            // identify the link by its location instead of the path it was reached by
+            WarningReason::SymlinkLoop(_) => warning
+                .path
+                .parent()
+                .and_then(|parent| fs::canonicalize(parent).ok())
+                .zip(warning.path.file_name())
+                .map_or_else(|| warning.path.clone(), |(parent, name)| parent.join(name)),
+            _ => warning.path.clone(),
+        };
+
+        !self
+            .seen_warnings
+            .insert((path, warning.reason.to_string()))
+            || self.sender.send(Err(warning)).is_ok()
+    }
+
+    /// `push()` adds a job to the current batch and runs the batch once it
+    /// is full. Returns `false` if the receiver is gone.
+    fn push(&mut self, job: IndexJob) -> bool {
+        self.batch.push(job);
+        self.batch.len() < BATCH_SIZE || self.flush()
+    }
+
+    /// `flush()` runs the jobs of the current batch in parallel and sends
+    /// the results in order. Jobs of files that were already found through
+    /// another path are skipped. Returns `false` if the receiver is gone.
+    fn flush(&mut self) -> bool {
+        let mut jobs = std::mem::take(&mut self.batch);
+
+        if self.deduplicate {
+            let roots = &self.roots;
+            let canonical_paths: Vec<PathBuf> = self.pool.install(|| {
+                jobs.par_iter()
+                    .map(|job| job.canonical_path(&roots[job.root]))
+                    .collect()
+            });
+            let mut canonical_paths = canonical_paths.into_iter();
+            jobs.retain(|_| {
+                canonical_paths
+                    .next()
+                    .is_some_and(|path| self.seen_files.insert(path))
+            });
+        }
+
+        let (sources, caches) = (&self.sources, &self.caches);
+        let results: Vec<(usize, PathBuf, FileIndex, Option<CacheEntry>)> =
+            self.pool.install(|| {
+                jobs.into_par_iter()
+                    .map(|job| {
+                        let (root, path) = (job.root, job.path.clone());
+                        let (index, cache_entry) = job.run(sources, caches[root].as_ref());
+                        (root, path, index, cache_entry)
+                    })
+                    .collect()
+            });
+
+        for (root, path, index, cache_entry) in results {
+            if let Some(cache) = &mut self.caches[root] {
+                cache.insert(&path, cache_entry);
+            }
+
+            for warning in index.warnings {
+                if !self.warn(warning) {
+                    return false;
+                }
+            }
+            for file in index.files {
+                if self.sender.send(Ok(file)).is_err() {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// `finish()` runs the remaining jobs and saves the caches.
+    fn finish(mut self) {
+        if !self.flush() {
+            return;
+        }
+
+        for cache in std::mem::take(&mut self.caches).into_iter().flatten() {
+            let file = cache.file().to_owned();
+            if let Err(e) = cache.save() {
+                self.warn(Warning {
+                    path: file,
+                    reason: WarningReason::Other(format!("failed to write cache: {e}")),
+                });
+            }
+        }
+    }
+}
+
+/// `TaggedFiles` is an iterator over the tagged files of one or more
+/// directories.
+///
+/// Files are yielded while the directories are still being searched, so the
+/// first files are available immediately and the files of huge directories
+/// never have to be held in memory at once. The files are searched and read
+/// on a background thread exactly like in `get_tags_from_roots()`, which
+/// collects all files of a `TaggedFiles`. Files and directories that can't
+/// be read are yielded as errors, while the search continues. If the search
+/// thread panics, the panic is yielded as the last error.
+pub struct TaggedFiles {
+    receiver: mpsc::Receiver<Result<TaggedFile, IndexError>>,
+    /// The search thread, which is joined once all results were received.
+    handle: Option<thread::JoinHandle<()>>,
+    /// The first searched directory, which panics are reported for.
+    directory: PathBuf,
+}
+
+impl TaggedFiles {
+    /// `walk()` starts searching a directory for tagged files.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if the given directory can't be read, a filter
+    /// is invalid or the thread pool can't be created.
+    pub fn walk(directory: &str, options: &SearchOptions) -> Result<Self, Error> {
+        Self::walk_roots(&[directory], options)
+    }
+
+    /// `walk_roots()` starts searching multiple directories for tagged files.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if one of the given directories can't be read,
+    /// a filter is invalid or the thread pool can't be created.
+    pub fn walk_roots(
+        directories: &[impl AsRef<str>],
+        options: &SearchOptions,
+    ) -> Result<Self, Error> {
+        Self::start(directories, options, None)
+    }
+
+    /// `walk_changed()` searches only the parts of the directories that are
+    /// affected by the changes, which have to be determined for the same
+    /// directories. Tags are inherited and ignore files apply exactly like
+    /// in a search of the whole directories. The cache isn't used, since
+    /// changed files have to be read again anyway.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if one of the given directories can't be read,
+    /// a filter is invalid or the thread pool can't be created.
+    pub fn walk_changed(
+        directories: &[impl AsRef<str>],
+        changes: &Changes,
+        options: &SearchOptions,
+    ) -> Result<Self, Error> {
+        let options = SearchOptions {
+            cache: None,
+            ..options.clone()
+        };
+        Self::start(directories, &options, Some(changes))
+    }
+
+    /// `start()` starts searching the directories, limited to the changed
+    /// parts if changes are given.
+    fn start(
+        directories: &[impl AsRef<str>],
+        options: &SearchOptions,
+        changes: Option<&Changes>,
+    ) -> Result<Self, Error> {
+        let mut walkers = Vec::new();
+        let mut roots = Vec::new();
+        for (root, directory) in directories.iter().enumerate() {
+            let directory = directory.as_ref();
+            // the walk reports all other errors as warnings
+            roots.push(fs::canonicalize(directory).map_err(Error::file(Path::new(directory)))?);
+            let mut walker = build_walker(directory, options)?;
+            let scopes = changes.map(|changes| Arc::new(changes.scopes[root].clone()));
+            if let Some(scopes) = &scopes {
+                let scopes = Arc::clone(scopes);
+                walker.filter_entry(move |entry| {
+                    scopes.iter().any(|scope| scope.leads_to(entry.path()))
+                });
+            }
+            let unchanged = scopes.is_some_and(|scopes| scopes.is_empty());
+            walkers.push((directory.to_string(), walker, unchanged));
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(BATCH_SIZE);
+        let mut indexer = Indexer {
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(options.threads)
+                .build()?,
+            sources: options.tag_sources(),
+            roots,
+            caches: walkers
+                .iter()
+                .map(|(directory, _, _)| {
+                    options
+                        .cache
+                        .as_ref()
+                        .filter(|_| options.sources.is_none())
+                        .map(|cache_dir| IndexCache::load(cache_dir, directory, options))
+                })
+                .collect(),
+            deduplicate: directories.len() > 1 || !options.no_follow,
+            seen_files: HashSet::new(),
+            seen_warnings: HashSet::new(),
+            batch: Vec::new(),
+            sender,
+        };
+
+        let directory = PathBuf::from(directories.first().map_or("", AsRef::as_ref));
+        let options = options.clone();
+        let handle = thread::spawn(move || {
+            for (root, (directory, walker, unchanged)) in walkers.iter().enumerate() {
+                if *unchanged {
+                    continue;
+                }
+                if !walk_directory(directory, root, walker, &options, &mut indexer) {
+                    return;
+                }
+            }
+            indexer.finish();
+        });
+
+        Ok(Self {
+            receiver,
+            handle: Some(handle),
+            directory,
+        })
+    }
+}
+
+impl Iterator for TaggedFiles {
+    type Item = Result<TaggedFile, IndexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(result) = self.receiver.recv() {
+            return Some(result);
+        }
+
+        // the search thread finished or panicked
+        let panic = self.handle.take()?.join().err()?;
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(ToString::to_string)
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+
+        Some(Err(Warning {
+            path: self.directory.clone(),
+            reason: WarningReason::Other(format!("the search stopped unexpectedly: {message}")),
+        }))
+    }
 }
 
 /// `get_tags_from_files()` recursively retrieves the tags of all files
 /// in a given directory.
 ///
+/// Tags of sidecar files (`file.pdf.tags`) and per-directory manifests
+/// (`.tags`) are added to the files they belong to. All files beneath a
+/// directory containing a `.tagdir` file inherit its tags. Files and
+/// directories that can't be read are skipped and reported as warnings.
+/// Files matched by ignore files and hidden files are skipped unless
+/// `SearchOptions::no_ignore` and `SearchOptions::hidden` are set, just
+/// like files that don't pass the filters of the options.
+///
+/// Files are read in parallel by `SearchOptions::threads` threads, but are
+/// always returned in the same order. Directories are walked by a single
+/// thread, because inherited tags are passed down the tree in order. Use
+/// `TaggedFiles` to process files while the directory is still being
+/// searched.
+///
 /// # Errors
 ///
-/// This function errors if it fails to walk the given directory.
-pub fn get_tags_from_files(directory: &str) -> Result<Vec<TaggedFile>, Box<dyn std::error::Error>> {
-    let mut tagged_files = Vec::new();
+/// This function errors if the given directory can't be read, a filter is
+/// invalid or the thread pool can't be created.
+pub fn get_tags_from_files(directory: &str, options: &SearchOptions) -> Result<FileIndex, Error> {
+    get_tags_from_roots(&[directory], options)
+}
 
-    for entry in WalkDir::new(directory).follow_links(true) {
-        let entry = entry?;
+/// `get_tags_from_roots()` works like `get_tags_from_files()`, but searches
+/// multiple directories.
+///
+/// Files are returned in the order of the given directories. If directories
+/// overlap or symbolic links lead to the same file, each file is only
+/// returned once, identified by its canonical path. Paths that don't go
+/// through symbolic links are preferred. Symbolic link loops are
+/// skipped and reported as warnings.
+///
+/// # Errors
+///
+/// This function errors if one of the given directories can't be read, a
+/// filter is invalid or the thread pool can't be created.
+pub fn get_tags_from_roots(
+    directories: &[impl AsRef<str>],
+    options: &SearchOptions,
+) -> Result<FileIndex, Error> {
+    let mut index = FileIndex::default();
 
-        if entry.file_type().is_dir() {
-            continue;
+    for result in TaggedFiles::walk_roots(directories, options)? {
+        match result {
+            Ok(file) => index.files.push(file),
+            Err(warning) => index.warnings.push(warning),
         }
+    }
+
+    Ok(index)
+}
 
-        let tags = get_tags_from_file(entry.path());
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+    use std::path::Path;
+    use std::sync::Arc;
 
-        if let Ok(tags) = tags {
-            tagged_files.push(TaggedFile {
-                path: entry.path().to_owned(),
-                tags,
-            });
+    use super::{
+        get_tags_from_file, get_tags_from_files, get_tags_from_roots, parse_manifest, Changes,
+        FileIndex, SearchOptions, TaggedFile, TaggedFiles, WarningReason,
+    };
+    use crate::error::Error;
+    use crate::sources::{TagSource, TaglineSource};
+
+    #[test]
+    fn test_parse_manifest() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_files: Vec<(&'a str, Vec<&'a str>)>,
+            expected_error: bool,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success",
+                input: "report.pdf: [#a #b]\n\nimage: 2.png:[#c]\n",
+                expected_files: vec![
+                    ("dir/report.pdf", vec!["#a", "#b"]),
+                    ("dir/image: 2.png", vec!["#c"]),
+                ],
+                expected_error: false,
+            },
+            TestCase {
+                name: "fail_no_colon",
+                input: "report.pdf [#a]",
+                expected_files: vec![],
+                expected_error: true,
+            },
+            TestCase {
+                name: "fail_no_taglist",
+                input: "report.pdf: #a",
+                expected_files: vec![],
+                expected_error: true,
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_parse_manifest: \n\t{}", test_case.name);
+
+            let res = parse_manifest(Path::new("dir"), test_case.input);
+            if res.is_err() {
+                assert!(test_case.expected_error);
+                continue;
+            }
+
+            assert!(!test_case.expected_error);
+
+            let files = res.unwrap();
+            let expected_files = test_case
+                .expected_files
+                .into_iter()
+                .map(|(path, tags)| {
+                    (
+                        Path::new(path).to_path_buf(),
+                        tags.into_iter().map(str::to_string).collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(expected_files, files);
+        }
+    }
+
+    #[test]
+    fn test_get_tags_from_file_with_custom_source() {
+        struct FirstWordSource;
+
+        impl TagSource for FirstWordSource {
+            fn get_tags(
+                &self,
+                _path: &Path,
+                reader: &mut dyn BufRead,
+            ) -> Result<Option<Vec<String>>, Error> {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+
+                Ok(line
+                    .split_whitespace()
+                    .next()
+                    .map(|word| vec![format!("#{word}")]))
+            }
+        }
+
+        struct TestCase<'a> {
+            name: &'a str,
+            sources: Vec<Arc<dyn TagSource>>,
+            expected_tags: Vec<&'a str>,
+        }
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "tags: [#a #first]\n").unwrap();
+
+        let mut appended = SearchOptions::default().default_tag_sources();
+        appended.push(Arc::new(FirstWordSource));
+
+        let test_cases = [
+            TestCase {
+                name: "success_appended",
+                sources: appended,
+                expected_tags: vec!["#a", "#first", "#tags:"],
+            },
+            TestCase {
+                name: "success_replaced",
+                sources: vec![Arc::new(FirstWordSource)],
+                expected_tags: vec!["#tags:"],
+            },
+            TestCase {
+                name: "success_reordered",
+                sources: vec![Arc::new(FirstWordSource), Arc::new(TaglineSource)],
+                expected_tags: vec!["#tags:", "#a", "#first"],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!(
+                "test_get_tags_from_file_with_custom_source: \n\t{}",
+                test_case.name
+            );
+
+            let options = SearchOptions {
+                sources: Some(test_case.sources),
+                ..Default::default()
+            };
+            let mut index = FileIndex::default();
+            let tags = get_tags_from_file(file.path(), &options.tag_sources(), &mut index);
+
+            assert!(index.warnings.is_empty());
+            assert_eq!(
+                Some(
+                    test_case
+                        .expected_tags
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect()
+                ),
+                tags
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_tags_from_files_with_tagdir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("acme/sub")).unwrap();
+        std::fs::create_dir_all(root.join("other")).unwrap();
+        std::fs::write(root.join("acme/.tagdir"), "tags: [#acme]\n").unwrap();
+        std::fs::write(root.join("acme/sub/a.md"), "tags: [#own #acme]\n").unwrap();
+        std::fs::write(root.join("acme/b.txt"), "untagged\n").unwrap();
+        std::fs::write(root.join("other/c.txt"), "untagged\n").unwrap();
+
+        let mut files = get_tags_from_files(root.to_str().unwrap(), &SearchOptions::default())
+            .unwrap()
+            .files;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        // `.tagdir` files above the searched directory are inherited as well
+        std::fs::write(root.join("acme/sub/d.txt"), "untagged\n").unwrap();
+        let sub_files = get_tags_from_files(
+            root.join("acme/sub").to_str().unwrap(),
+            &SearchOptions::default(),
+        )
+        .unwrap()
+        .files;
+
+        let files = files
+            .into_iter()
+            .map(|file| (file.path, file.tags, file.inherited_tags))
+            .collect::<Vec<_>>();
+        let sub_files = sub_files
+            .into_iter()
+            .map(|file| (file.path, file.inherited_tags))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (
+                    root.join("acme/b.txt"),
+                    vec!["#acme".to_string()],
+                    vec!["#acme".to_string()]
+                ),
+                (
+                    root.join("acme/sub/a.md"),
+                    vec!["#own".to_string(), "#acme".to_string()],
+                    vec![]
+                ),
+            ],
+            files
+        );
+        assert_eq!(
+            vec![
+                (root.join("acme/sub/a.md"), vec![]),
+                (root.join("acme/sub/d.txt"), vec!["#acme".to_string()]),
+            ],
+            sub_files
+        );
+    }
+
+    #[test]
+    fn test_get_tags_from_files_with_sidecars() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.pdf"), "").unwrap();
+        std::fs::write(root.join("a.pdf.tags"), "tags: [#side]\n").unwrap();
+        std::fs::write(root.join("orphan.tags"), "tags: [#orphan]\n").unwrap();
+        std::fs::write(root.join("sub/.tags"), "b.pdf: [#b]\n").unwrap();
+        std::fs::write(root.join("sub/b.pdf"), "").unwrap();
+
+        let files = get_tags_from_files(root.to_str().unwrap(), &SearchOptions::default())
+            .unwrap()
+            .files;
+
+        let files = files
+            .into_iter()
+            .map(|file| (file.path, file.tags))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (root.join("a.pdf"), vec!["#side".to_string()]),
+                (root.join("orphan.tags"), vec!["#orphan".to_string()]),
+                (root.join("sub/b.pdf"), vec!["#b".to_string()]),
+            ],
+            files
+        );
+    }
+
+    #[test]
+    fn test_get_tags_from_files_single_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("acme")).unwrap();
+        std::fs::write(root.join("acme/.tagdir"), "tags: [#acme]\n").unwrap();
+        std::fs::write(root.join("acme/.tags"), "a.pdf: [#listed]\n").unwrap();
+        std::fs::write(root.join("acme/a.pdf"), "").unwrap();
+        std::fs::write(root.join("acme/a.pdf.tags"), "tags: [#side]\n").unwrap();
+
+        let path = root.join("acme/a.pdf");
+        let mut files = get_tags_from_files(path.to_str().unwrap(), &SearchOptions::default())
+            .unwrap()
+            .files;
+
+        assert_eq!(1, files.len());
+        let file = files.remove(0);
+        assert_eq!(path, file.path);
+        assert_eq!(
+            vec![
+                "#side".to_string(),
+                "#listed".to_string(),
+                "#acme".to_string()
+            ],
+            file.tags
+        );
+    }
+
+    #[test]
+    fn test_get_tags_from_files_warnings() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("ok.md"), "tags: [#a]\n").unwrap();
+        std::fs::write(root.join("malformed.md"), "tags: [#a ##]\n").unwrap();
+        std::fs::write(root.join("binary.md"), "tags: [#a\0]\n").unwrap();
+        std::fs::write(root.join("untagged.md"), "no tagline\n").unwrap();
+
+        let mut index =
+            get_tags_from_files(root.to_str().unwrap(), &SearchOptions::default()).unwrap();
+        index.warnings.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(1, index.files.len());
+        assert_eq!(2, index.warnings.len());
+        assert_eq!(root.join("binary.md"), index.warnings[0].path);
+        assert!(matches!(index.warnings[0].reason, WarningReason::Decode));
+        assert_eq!(root.join("malformed.md"), index.warnings[1].path);
+        assert!(matches!(
+            index.warnings[1].reason,
+            WarningReason::MalformedTagline(_)
+        ));
+    }
+
+    #[test]
+    fn test_get_tags_from_files_missing_root() {
+        assert!(get_tags_from_files("/nonexistent/tag/root", &SearchOptions::default()).is_err());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let roots = [temp_dir.path().to_str().unwrap(), "/nonexistent/tag/root"];
+        let e = get_tags_from_roots(&roots, &SearchOptions::default()).unwrap_err();
+        assert!(
+            matches!(&e, Error::File { path, .. } if path == Path::new("/nonexistent/tag/root"))
+        );
+        assert_eq!("failed to access /nonexistent/tag/root", e.to_string());
+    }
+
+    /// `create_test_tree()` creates a temporary directory with `count` tagged
+    /// files spread over multiple subdirectories. The directory is removed
+    /// when the returned handle is dropped, even if the test fails.
+    fn create_test_tree(count: usize) -> tempfile::TempDir {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+
+        for i in 0..count {
+            let directory = root.join(format!("dir{}", i % 16));
+            std::fs::create_dir_all(&directory).unwrap();
+            std::fs::write(
+                directory.join(format!("file{i}.md")),
+                format!("tags: [#file{i} #mod{}]\ncontent\n", i % 7),
+            )
+            .unwrap();
         }
+
+        temp_dir
     }
 
-    Ok(tagged_files.clone())
+    #[test]
+    fn test_get_tags_from_files_parallel_order() {
+        let temp_dir = create_test_tree(200);
+        let root = temp_dir.path();
+
+        let get_paths = |threads| {
+            let options = SearchOptions {
+                threads,
+                ..Default::default()
+            };
+            get_tags_from_files(root.to_str().unwrap(), &options)
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|file| (file.path, file.tags))
+                .collect::<Vec<_>>()
+        };
+
+        let serial = get_paths(1);
+        let parallel = get_paths(8);
+
+        assert_eq!(200, serial.len());
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn test_get_tags_from_roots() {
+        struct TestCase<'a> {
+            name: &'a str,
+            roots: Vec<&'a str>,
+            expected_paths: Vec<&'a str>,
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("notes")).unwrap();
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("notes/a.md"), "tags: [#a]\n").unwrap();
+        std::fs::write(root.join("docs/b.md"), "tags: [#b]\n").unwrap();
+
+        let test_cases = [
+            TestCase {
+                name: "success_separate",
+                roots: vec!["docs", "notes"],
+                expected_paths: vec!["docs/b.md", "notes/a.md"],
+            },
+            TestCase {
+                name: "success_overlapping",
+                roots: vec!["notes", ".", "notes/../notes"],
+                expected_paths: vec!["notes/a.md", "./docs/b.md"],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_get_tags_from_roots: \n\t{}", test_case.name);
+
+            let roots = test_case
+                .roots
+                .iter()
+                .map(|path| root.join(path).to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            let paths = get_tags_from_roots(&roots, &SearchOptions::default())
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|file| file.path)
+                .collect::<Vec<_>>();
+            let expected_paths = test_case
+                .expected_paths
+                .iter()
+                .map(|path| root.join(path))
+                .collect::<Vec<_>>();
+
+            assert_eq!(expected_paths, paths);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_tags_from_files_symlinks() {
+        struct TestCase<'a> {
+            name: &'a str,
+            no_follow: bool,
+            expected_paths: Vec<&'a str>,
+            expected_loops: usize,
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("notes")).unwrap();
+        std::fs::write(root.join("notes/a.md"), "tags: [#a]\n").unwrap();
+        std::os::unix::fs::symlink(root.join("notes"), root.join("link")).unwrap();
+        std::os::unix::fs::symlink(root.join("notes/a.md"), root.join("b.md")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("notes/loop")).unwrap();
+
+        let test_cases = [
+            TestCase {
+                name: "success_follow",
+                no_follow: false,
+                expected_paths: vec!["notes/a.md"],
+                expected_loops: 1,
+            },
+            TestCase {
+                name: "success_no_follow",
+                no_follow: true,
+                expected_paths: vec!["notes/a.md"],
+                expected_loops: 0,
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_get_tags_from_files_symlinks: \n\t{}", test_case.name);
+
+            let options = SearchOptions {
+                no_follow: test_case.no_follow,
+                ..Default::default()
+            };
+            let index = get_tags_from_files(root.to_str().unwrap(), &options).unwrap();
+
+            let paths = index
+                .files
+                .into_iter()
+                .map(|file| file.path)
+                .collect::<Vec<_>>();
+            let expected_paths = test_case
+                .expected_paths
+                .iter()
+                .map(|path| root.join(path))
+                .collect::<Vec<_>>();
+            let loops = index
+                .warnings
+                .iter()
+                .filter(|warning| matches!(warning.reason, WarningReason::SymlinkLoop(_)))
+                .count();
+
+            assert_eq!(expected_paths, paths);
+            assert_eq!(test_case.expected_loops, loops);
+        }
+    }
+
+    #[test]
+    fn test_get_tags_from_files_cache() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let cache_dir = root.join(".cache");
+        std::fs::write(root.join("a.md"), "tags: [#a]\n").unwrap();
+        std::fs::write(root.join("b.md"), "tags: [#b]\n").unwrap();
+
+        let options = SearchOptions {
+            cache: Some(cache_dir.clone()),
+            ..Default::default()
+        };
+        let get_tags = || {
+            get_tags_from_files(root.to_str().unwrap(), &options)
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|file| file.tags)
+                .collect::<Vec<_>>()
+        };
+
+        let uncached = get_tags();
+        assert_eq!(1, std::fs::read_dir(&cache_dir).unwrap().count());
+        assert_eq!(uncached, get_tags());
+
+        std::fs::write(root.join("b.md"), "tags: [#b #c]\n").unwrap();
+        std::fs::remove_file(root.join("a.md")).unwrap();
+        assert_eq!(vec![vec!["#b", "#c"]], get_tags());
+    }
+
+    #[test]
+    fn test_tagged_files() {
+        let temp_dir = create_test_tree(1000);
+        let root = temp_dir.path();
+
+        let index = get_tags_from_files(root.to_str().unwrap(), &SearchOptions::default()).unwrap();
+        let files = TaggedFiles::walk(root.to_str().unwrap(), &SearchOptions::default())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // stopping early must not block the search thread
+        let first = TaggedFiles::walk(root.to_str().unwrap(), &SearchOptions::default())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+
+        let paths = |files: &[TaggedFile]| {
+            files
+                .iter()
+                .map(|file| (file.path.clone(), file.tags.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(1000, files.len());
+        assert_eq!(paths(&index.files), paths(&files));
+        assert_eq!(index.files[0].path, first.path);
+    }
+
+    #[test]
+    fn test_tagged_files_panic() {
+        struct PanicSource;
+
+        impl TagSource for PanicSource {
+            fn get_tags(
+                &self,
+                _path: &Path,
+                _reader: &mut dyn BufRead,
+            ) -> Result<Option<Vec<String>>, Error> {
+                panic!("broken source");
+            }
+        }
+
+        let temp_dir = create_test_tree(1);
+        let options = SearchOptions {
+            sources: Some(vec![Arc::new(PanicSource)]),
+            ..Default::default()
+        };
+
+        let results = TaggedFiles::walk(temp_dir.path().to_str().unwrap(), &options)
+            .unwrap()
+            .collect::<Vec<_>>();
+
+        assert_eq!(1, results.len());
+        let warning = results.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(temp_dir.path(), warning.path);
+        assert!(warning.reason.to_string().contains("broken source"));
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test -- --ignored --nocapture`"]
+    fn bench_get_tags_from_files() {
+        let temp_dir = create_test_tree(20_000);
+        let root = temp_dir.path();
+
+        for threads in [1, 2, 4, 0] {
+            let options = SearchOptions {
+                threads,
+                ..Default::default()
+            };
+
+            let start = std::time::Instant::now();
+            let index = get_tags_from_files(root.to_str().unwrap(), &options).unwrap();
+
+            println!(
+                "bench_get_tags_from_files: {} files with {threads} threads in {:?}",
+                index.files.len(),
+                start.elapsed()
+            );
+        }
+    }
+
+    #[test]
+    fn test_tagged_files_walk_changed() {
+        struct TestCase<'a> {
+            name: &'a str,
+            changed: Vec<&'a str>,
+            expected: Vec<(&'a str, Vec<&'a str>)>,
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("acme/sub")).unwrap();
+        std::fs::create_dir_all(root.join("other")).unwrap();
+        std::fs::create_dir_all(root.join("ignored")).unwrap();
+        std::fs::write(root.join(".tagignore"), "ignored/\n").unwrap();
+        std::fs::write(root.join("acme/.tagdir"), "tags: [#acme]\n").unwrap();
+        std::fs::write(root.join("acme/sub/a.md"), "tags: [#a]\n").unwrap();
+        std::fs::write(root.join("acme/b.md"), "tags: [#b]\n").unwrap();
+        std::fs::write(root.join("other/c.pdf"), "").unwrap();
+        std::fs::write(root.join("other/c.pdf.tags"), "tags: [#c]\n").unwrap();
+        std::fs::write(root.join("other/.tags"), "d.pdf: [#d]\n").unwrap();
+        std::fs::write(root.join("other/d.pdf"), "").unwrap();
+        std::fs::write(root.join("other/e.md"), "tags: [#e]\n").unwrap();
+        std::fs::write(root.join("ignored/f.md"), "tags: [#f]\n").unwrap();
+
+        let test_cases = [
+            TestCase {
+                name: "file",
+                changed: vec!["acme/sub/a.md"],
+                expected: vec![("acme/sub/a.md", vec!["#a", "#acme"])],
+            },
+            TestCase {
+                name: "tagdir",
+                changed: vec!["acme/.tagdir"],
+                expected: vec![
+                    ("acme/b.md", vec!["#b", "#acme"]),
+                    ("acme/sub/a.md", vec!["#a", "#acme"]),
+                ],
+            },
+            TestCase {
+                name: "sidecar",
+                changed: vec!["other/c.pdf.tags"],
+                expected: vec![("other/c.pdf", vec!["#c"])],
+            },
+            TestCase {
+                name: "manifest",
+                changed: vec!["other/.tags"],
+                expected: vec![
+                    ("other/c.pdf", vec!["#c"]),
+                    ("other/d.pdf", vec!["#d"]),
+                    ("other/e.md", vec!["#e"]),
+                ],
+            },
+            TestCase {
+                name: "ignored",
+                changed: vec!["ignored/f.md"],
+                expected: vec![],
+            },
+            TestCase {
+                name: "multiple",
+                changed: vec!["acme/b.md", "other/e.md"],
+                expected: vec![
+                    ("acme/b.md", vec!["#b", "#acme"]),
+                    ("other/e.md", vec!["#e"]),
+                ],
+            },
+        ];
+
+        let directories = [root.to_str().unwrap()];
+        let canonical_root = std::fs::canonicalize(root).unwrap();
+        for test_case in test_cases {
+            println!("test_tagged_files_walk_changed: \n\t{}", test_case.name);
+
+            let changed_paths = test_case
+                .changed
+                .iter()
+                .map(|path| canonical_root.join(path))
+                .collect::<Vec<_>>();
+            let changes = Changes::new(&directories, &changed_paths).unwrap();
+            for path in &test_case.changed {
+                assert!(changes.contains(&root.join(path)));
+            }
+
+            let files =
+                TaggedFiles::walk_changed(&directories, &changes, &SearchOptions::default())
+                    .unwrap()
+                    .map(|file| {
+                        let file = file.unwrap();
+                        (file.path, file.tags)
+                    })
+                    .collect::<Vec<_>>();
+
+            let expected = test_case
+                .expected
+                .into_iter()
+                .map(|(path, tags)| {
+                    (
+                        root.join(path),
+                        tags.into_iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(expected, files);
+        }
+
+        // removed directories contain the files that were found beneath them
+        let changes = Changes::new(&directories, &[canonical_root.join("gone")]).unwrap();
+        assert!(changes.contains(&root.join("gone/g.md")));
+        assert!(!changes.contains(&root.join("other/e.md")));
+    }
+
+    #[test]
+    fn test_get_tags_from_files_ignore() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("ignored")).unwrap();
+        std::fs::create_dir_all(root.join("archive")).unwrap();
+        std::fs::write(root.join(".tagignore"), "ignored/\n").unwrap();
+        std::fs::write(root.join(".ignore"), "archive/\n").unwrap();
+        std::fs::write(root.join(".tags"), "b.pdf: [#b]\n").unwrap();
+        std::fs::write(root.join("a.md"), "tags: [#a]\n").unwrap();
+        std::fs::write(root.join("b.pdf"), "").unwrap();
+        std::fs::write(root.join(".hidden.md"), "tags: [#hidden]\n").unwrap();
+        std::fs::write(root.join("ignored/c.md"), "tags: [#c]\n").unwrap();
+        std::fs::write(root.join("archive/d.md"), "tags: [#d]\n").unwrap();
+
+        let get_paths = |no_ignore, hidden| {
+            let options = SearchOptions {
+                no_ignore,
+                hidden,
+                ..Default::default()
+            };
+            let mut paths = get_tags_from_files(root.to_str().unwrap(), &options)
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|file| file.path.strip_prefix(root).unwrap().to_owned())
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        let respected = get_paths(false, false);
+        let all = get_paths(true, true);
+
+        assert_eq!(vec![Path::new("a.md"), Path::new("b.pdf")], respected);
+        assert_eq!(
+            vec![
+                Path::new(".hidden.md"),
+                Path::new("a.md"),
+                Path::new("archive/d.md"),
+                Path::new("b.pdf"),
+                Path::new("ignored/c.md"),
+            ],
+            all
+        );
+    }
+
+    #[test]
+    fn test_get_tags_from_files_filters() {
+        struct TestCase<'a> {
+            name: &'a str,
+            options: SearchOptions,
+            expected_paths: Vec<&'a str>,
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("archive/old")).unwrap();
+        std::fs::write(root.join("a.md"), "tags: [#a]\n").unwrap();
+        std::fs::write(root.join("b.txt"), "tags: [#b]\n").unwrap();
+        std::fs::write(root.join("c.org"), "tags: [#c]\n".repeat(100)).unwrap();
+        std::fs::write(root.join("archive/d.md"), "tags: [#d]\n").unwrap();
+        std::fs::write(root.join("archive/old/e.md"), "tags: [#e]\n").unwrap();
+
+        let test_cases = [
+            TestCase {
+                name: "success_include",
+                options: SearchOptions {
+                    include: vec!["*.md".to_string()],
+                    ..Default::default()
+                },
+                expected_paths: vec!["a.md", "archive/d.md", "archive/old/e.md"],
+            },
+            TestCase {
+                name: "success_exclude",
+                options: SearchOptions {
+                    exclude: vec!["archive/**".to_string(), "*.org".to_string()],
+                    ..Default::default()
+                },
+                expected_paths: vec!["a.md", "b.txt"],
+            },
+            TestCase {
+                name: "success_types",
+                options: SearchOptions {
+                    types: vec!["txt".to_string(), "org".to_string()],
+                    ..Default::default()
+                },
+                expected_paths: vec!["b.txt", "c.org"],
+            },
+            TestCase {
+                name: "success_depth",
+                options: SearchOptions {
+                    min_depth: Some(2),
+                    max_depth: Some(2),
+                    ..Default::default()
+                },
+                expected_paths: vec!["archive/d.md"],
+            },
+            TestCase {
+                name: "success_max_filesize",
+                options: SearchOptions {
+                    max_filesize: Some(100),
+                    ..Default::default()
+                },
+                expected_paths: vec!["a.md", "archive/d.md", "archive/old/e.md", "b.txt"],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_get_tags_from_files_filters: \n\t{}", test_case.name);
+
+            let paths = get_tags_from_files(root.to_str().unwrap(), &test_case.options)
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|file| file.path)
+                .collect::<Vec<_>>();
+            let expected_paths = test_case
+                .expected_paths
+                .iter()
+                .map(|path| root.join(path))
+                .collect::<Vec<_>>();
+
+            assert_eq!(expected_paths, paths);
+        }
+
+        assert!(get_tags_from_files(
+            root.to_str().unwrap(),
+            &SearchOptions {
+                types: vec!["no-such-type".to_string()],
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
 }
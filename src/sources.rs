@@ -0,0 +1,546 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use pest::Parser;
+
+use crate::attributes::read_xattr_tags;
+use crate::error::Error;
+use crate::formats;
+use crate::parsers::onfile::{self, TaglineParser};
+
+/// `TagSource` is a source of tags for files, like the tagline at the start
+/// of a file. Sources can be chained with `SearchOptions::sources`.
+pub trait TagSource: Send + Sync {
+    /// `get_tags()` returns the tags of the file at `path`, whose content
+    /// can be read from `reader`. It returns `None` if the file isn't tagged
+    /// by this source. Files starting with a UTF-16 BOM are transcoded
+    /// to UTF-8 and a UTF-8 BOM is removed; `read_text()` can be used to
+    /// decode the remaining content.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if the file can't be read. Custom sources can
+    /// return their own errors with `Error::Other`.
+    fn get_tags(&self, path: &Path, reader: &mut dyn BufRead)
+        -> Result<Option<Vec<String>>, Error>;
+}
+
+/// `DecodeError` is returned if the content of a file isn't text.
+#[derive(Debug)]
+pub struct DecodeError;
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "content can't be decoded as text")
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// `text_reader()` returns a reader that transcodes content starting with
+/// a UTF-16 BOM to UTF-8 and removes a UTF-8 BOM. All other content is
+/// passed through unchanged.
+pub fn text_reader<R: Read>(reader: R) -> BufReader<DecodeReaderBytes<R, Vec<u8>>> {
+    BufReader::new(
+        DecodeReaderBytesBuilder::new()
+            .utf8_passthru(true)
+            .strip_bom(true)
+            .build(reader),
+    )
+}
+
+/// `decode_text()` decodes bytes as UTF-8 and falls back to Latin-1
+/// if they aren't valid UTF-8.
+///
+/// # Errors
+///
+/// This function errors if the bytes contain a null byte, which
+/// doesn't appear in text files.
+pub fn decode_text(bytes: Vec<u8>) -> Result<String, DecodeError> {
+    if bytes.contains(&0) {
+        return Err(DecodeError);
+    }
+
+    Ok(String::from_utf8(bytes)
+        .unwrap_or_else(|e| e.into_bytes().into_iter().map(char::from).collect()))
+}
+
+/// `read_text()` reads and decodes the remaining content of a reader.
+///
+/// # Errors
+///
+/// This function errors if reading fails or the content isn't text.
+pub fn read_text(reader: &mut dyn BufRead) -> Result<String, Error> {
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content)?;
+
+    Ok(decode_text(content)?)
+}
+
+/// `TaglineSource` reads the tagline at the start of a file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaglineSource;
+
+impl TagSource for TaglineSource {
+    fn get_tags(
+        &self,
+        _path: &Path,
+        mut reader: &mut dyn BufRead,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let tagline = read_tagline(&mut reader)?;
+
+        match get_tags_from_tagline(&tagline) {
+            Ok(tags) => Ok(Some(tags)),
+            // only report lines that were meant to be a tagline
+            Err(e) if tagline.trim_start().starts_with("tags:") => Err(e),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// `NativeFormatSource` reads the native tag syntax of Org and `AsciiDoc` files.
+/// Only the first `MAX_CONTENT_BYTES` of a file are read.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NativeFormatSource;
+
+impl TagSource for NativeFormatSource {
+    fn get_tags(
+        &self,
+        path: &Path,
+        reader: &mut dyn BufRead,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let Some(get_native_tags) = get_native_format(path) else {
+            return Ok(None);
+        };
+
+        let content = decode_text(read_start(reader)?)?;
+
+        let tags = get_native_tags(&content);
+        Ok((!tags.is_empty()).then_some(tags))
+    }
+}
+
+/// `MAX_CONTENT_BYTES` is the amount of bytes at the start of a file that
+/// are searched for inline tags and native tags, so that huge files aren't
+/// read into memory at once.
+const MAX_CONTENT_BYTES: u64 = 1 << 20;
+
+/// `read_start()` reads the first `MAX_CONTENT_BYTES` of a reader. Content
+/// that is cut off ends at its last complete line, so that it doesn't end
+/// in a partial tag.
+fn read_start(reader: &mut dyn BufRead) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    reader.take(MAX_CONTENT_BYTES).read_to_end(&mut content)?;
+
+    if content.len() as u64 == MAX_CONTENT_BYTES {
+        let end = content.iter().rposition(|&byte| byte == b'\n').unwrap_or(0);
+        content.truncate(end);
+    }
+
+    Ok(content)
+}
+
+/// `InlineTagSource` collects `#hashtags` found in the first
+/// `MAX_CONTENT_BYTES` of a file. Files that aren't text are skipped.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InlineTagSource;
+
+impl TagSource for InlineTagSource {
+    fn get_tags(
+        &self,
+        _path: &Path,
+        reader: &mut dyn BufRead,
+    ) -> Result<Option<Vec<String>>, Error> {
+        // binary files like images don't contain inline tags
+        let Ok(content) = decode_text(read_start(reader)?) else {
+            return Ok(None);
+        };
+
+        let tags = get_inline_tags(&content);
+        Ok((!tags.is_empty()).then_some(tags))
+    }
+}
+
+/// `XattrSource` reads tags stored in the `user.xdg.tags` extended attribute.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XattrSource;
+
+impl TagSource for XattrSource {
+    fn get_tags(
+        &self,
+        path: &Path,
+        _reader: &mut dyn BufRead,
+    ) -> Result<Option<Vec<String>>, Error> {
+        match read_xattr_tags(path) {
+            Ok(tags) => Ok((!tags.is_empty()).then_some(tags)),
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// `MAX_TAGLINE_LINES` is the maximum amount of lines a tagline may span.
+const MAX_TAGLINE_LINES: usize = 32;
+
+/// `MAX_TAGLINE_BYTES` is the maximum amount of bytes read for a tagline, so
+/// that a huge first line like in a minified or binary file isn't read into
+/// memory at once.
+const MAX_TAGLINE_BYTES: u64 = 64 << 10;
+
+/// `read_tagline()` reads the tagline at the start of a reader.
+/// A tagline may continue over multiple lines as long as its taglist is
+/// not closed yet. Reading stops after the first line if it doesn't start
+/// with `tags:` and after `MAX_TAGLINE_LINES` lines or `MAX_TAGLINE_BYTES`
+/// bytes at the latest. A first line that can't be decoded is treated as an
+/// empty tagline.
+///
+/// It will return an error if reading fails or a line that looks like
+/// a tagline can't be decoded.
+pub(crate) fn read_tagline(reader: &mut impl BufRead) -> Result<String, Error> {
+    let mut tagline = String::new();
+    let mut reader = reader.take(MAX_TAGLINE_BYTES);
+
+    for _ in 0..MAX_TAGLINE_LINES {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+
+        let is_first_line = tagline.is_empty();
+        let looks_like_tagline = !is_first_line || line.trim_ascii_start().starts_with(b"tags:");
+
+        match decode_text(line) {
+            Ok(line) => tagline.push_str(&line),
+            Err(e) if looks_like_tagline => return Err(e.into()),
+            Err(_) => break,
+        }
+
+        if !looks_like_tagline || tagline.contains(']') {
+            break;
+        }
+    }
+
+    Ok(tagline)
+}
+
+/// `get_tags_from_tagline()` returns the tags of a tagline.
+/// It will return an error if the tagline can't be parsed.
+pub(crate) fn get_tags_from_tagline(tagline: &str) -> Result<Vec<String>, Error> {
+    let parsed = TaglineParser::parse(onfile::Rule::tagline, tagline.trim())?;
+
+    let mut tags = Vec::new();
+
+    for tag in parsed {
+        if tag.as_rule() == onfile::Rule::tag {
+            tags.push(tag.as_str().to_string());
+        }
+    }
+
+    Ok(tags)
+}
+
+/// `get_inline_tags()` returns all `#hashtags` found in a text.
+/// A hashtag must stand at the start of a word. Code fences, inline code
+/// and URLs are skipped.
+fn get_inline_tags(content: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut in_code_fence = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_fence = !in_code_fence;
+            continue;
+        }
+
+        if in_code_fence {
+            continue;
+        }
+
+        // every odd segment between backticks is inline code
+        for text in line.split('`').step_by(2) {
+            for word in text.split_whitespace() {
+                if !word.starts_with('#') || word.contains("://") {
+                    continue;
+                }
+
+                let Ok(mut parsed) = TaglineParser::parse(onfile::Rule::tag, word) else {
+                    continue;
+                };
+
+                if let Some(tag) = parsed.next() {
+                    let tag = tag.as_str().to_string();
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+            }
+        }
+    }
+
+    tags
+}
+
+/// `get_native_format()` returns the tag parser of a file format that
+/// has its own syntax for tags, based on the file extension.
+fn get_native_format(file: &Path) -> Option<fn(&str) -> Vec<String>> {
+    match file.extension()?.to_str()? {
+        "org" => Some(formats::get_org_tags),
+        "adoc" | "asciidoc" | "asc" => Some(formats::get_asciidoc_tags),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use std::path::Path;
+
+    use super::{
+        decode_text, get_inline_tags, read_tagline, text_reader, InlineTagSource,
+        NativeFormatSource, TagSource, MAX_CONTENT_BYTES, MAX_TAGLINE_BYTES,
+    };
+
+    #[test]
+    fn test_read_tagline() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_tagline: &'a str,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_single_line",
+                input: "tags: [#a #b]\nsome content\n",
+                expected_tagline: "tags: [#a #b]\n",
+            },
+            TestCase {
+                name: "success_multi_line",
+                input: "tags: [#a\n  #b\n  #c]\nsome content\n",
+                expected_tagline: "tags: [#a\n  #b\n  #c]\n",
+            },
+            TestCase {
+                name: "success_no_tagline",
+                input: "some content\nmore content\n",
+                expected_tagline: "some content\n",
+            },
+            TestCase {
+                name: "success_crlf",
+                input: "tags: [#a\r\n  #b]\r\nsome content\r\n",
+                expected_tagline: "tags: [#a\r\n  #b]\r\n",
+            },
+            TestCase {
+                name: "success_unclosed_taglist",
+                input: "tags: [#a\n#b\n",
+                expected_tagline: "tags: [#a\n#b\n",
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_read_tagline: \n\t{}", test_case.name);
+
+            let tagline = read_tagline(&mut Cursor::new(test_case.input)).unwrap();
+
+            assert_eq!(test_case.expected_tagline, tagline);
+        }
+    }
+
+    #[test]
+    fn test_get_inline_tags() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a str,
+            expected_tags: Vec<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_words",
+                input: "some #inline text\n#tag-2 at the start and #inline again",
+                expected_tags: vec!["#inline", "#tag-2"],
+            },
+            TestCase {
+                name: "success_skip_headings",
+                input: "# Heading\n## Second heading\ntext #tag",
+                expected_tags: vec!["#tag"],
+            },
+            TestCase {
+                name: "success_skip_code",
+                input: "```\n#not_a_tag\n```\n`#code` #tag `#more_code`",
+                expected_tags: vec!["#tag"],
+            },
+            TestCase {
+                name: "success_skip_urls",
+                input: "https://example.com/#anchor and a#b but #tag",
+                expected_tags: vec!["#tag"],
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_get_inline_tags: \n\t{}", test_case.name);
+
+            assert_eq!(test_case.expected_tags, get_inline_tags(test_case.input));
+        }
+    }
+
+    #[test]
+    fn test_native_format_source_limit() {
+        let mut input = b"#+FILETAGS: :first:\n".to_vec();
+        input.resize(usize::try_from(MAX_CONTENT_BYTES).unwrap(), b'\n');
+        input.extend(b"#+FILETAGS: :after_limit:\n");
+
+        let tags = NativeFormatSource
+            .get_tags(Path::new("a.org"), &mut Cursor::new(input))
+            .unwrap();
+
+        assert_eq!(Some(vec!["#first".to_string()]), tags);
+    }
+
+    #[test]
+    fn test_inline_tag_source() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: Vec<u8>,
+            expected_tags: Option<Vec<&'a str>>,
+        }
+
+        let mut long = "text #first\n".repeat(2).into_bytes();
+        long.resize(usize::try_from(MAX_CONTENT_BYTES).unwrap() - 4, b' ');
+        long.extend(b"#cut #after_limit\n");
+
+        let test_cases = [
+            TestCase {
+                name: "success_text",
+                input: b"text #a and #b".to_vec(),
+                expected_tags: Some(vec!["#a", "#b"]),
+            },
+            TestCase {
+                name: "success_binary",
+                input: b"\x89PNG\r\n\x1a\n\x00\x00 #a".to_vec(),
+                expected_tags: None,
+            },
+            TestCase {
+                name: "success_limit",
+                input: long,
+                expected_tags: Some(vec!["#first"]),
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_inline_tag_source: \n\t{}", test_case.name);
+
+            let tags = InlineTagSource
+                .get_tags(Path::new("a.md"), &mut Cursor::new(test_case.input))
+                .unwrap();
+
+            assert_eq!(
+                test_case
+                    .expected_tags
+                    .map(|tags| tags.into_iter().map(str::to_string).collect::<Vec<_>>()),
+                tags
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_text() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: &'a [u8],
+            expected_text: Option<&'a str>,
+        }
+
+        let test_cases = [
+            TestCase {
+                name: "success_utf8",
+                input: "tags: [#café]".as_bytes(),
+                expected_text: Some("tags: [#café]"),
+            },
+            TestCase {
+                name: "success_latin1",
+                input: b"tags: [#caf\xe9]",
+                expected_text: Some("tags: [#café]"),
+            },
+            TestCase {
+                name: "fail_binary",
+                input: b"tags: [#a]\x00\x01",
+                expected_text: None,
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_decode_text: \n\t{}", test_case.name);
+
+            assert_eq!(
+                test_case.expected_text.map(str::to_string),
+                decode_text(test_case.input.to_vec()).ok()
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_tagline_with_bom() {
+        struct TestCase<'a> {
+            name: &'a str,
+            input: Vec<u8>,
+            expected_tagline: &'a str,
+        }
+
+        let utf16 = |bytes: &[u8], text: &str, encode: fn(u16) -> [u8; 2]| {
+            let mut input = bytes.to_vec();
+            input.extend(text.encode_utf16().flat_map(encode));
+            input
+        };
+
+        let test_cases = [
+            TestCase {
+                name: "success_utf8_bom",
+                input: b"\xef\xbb\xbftags: [#a]\ncontent".to_vec(),
+                expected_tagline: "tags: [#a]\n",
+            },
+            TestCase {
+                name: "success_utf16le_bom",
+                input: utf16(b"\xff\xfe", "tags: [#a\n#b]\ncontent", u16::to_le_bytes),
+                expected_tagline: "tags: [#a\n#b]\n",
+            },
+            TestCase {
+                name: "success_utf16be_bom",
+                input: utf16(b"\xfe\xff", "tags: [#a]\r\ncontent", u16::to_be_bytes),
+                expected_tagline: "tags: [#a]\r\n",
+            },
+        ];
+
+        for test_case in test_cases {
+            println!("test_read_tagline_with_bom: \n\t{}", test_case.name);
+
+            let tagline = read_tagline(&mut text_reader(test_case.input.as_slice())).unwrap();
+
+            assert_eq!(test_case.expected_tagline, tagline);
+        }
+    }
+
+    #[test]
+    fn test_read_tagline_long_line() {
+        let mut input = b"tags: [#a".to_vec();
+        input.resize(usize::try_from(MAX_TAGLINE_BYTES).unwrap() * 4, b'a');
+
+        let tagline = read_tagline(&mut Cursor::new(input)).unwrap();
+
+        assert_eq!(usize::try_from(MAX_TAGLINE_BYTES).unwrap(), tagline.len());
+    }
+
+    #[test]
+    fn test_read_tagline_decode_error() {
+        assert!(read_tagline(&mut Cursor::new(b"tags: [#a\x00]\n")).is_err());
+        assert_eq!(
+            String::new(),
+            read_tagline(&mut Cursor::new(b"\x89PNG\x00\n")).unwrap()
+        );
+    }
+}
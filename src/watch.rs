@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use notify::{event::EventKind, Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error::Error;
+use crate::search::{filter_ignored, SearchOptions};
+
+/// `DEBOUNCE` is how long `IndexWatcher::wait()` waits for further changes
+/// after a change was noticed, so that a burst of changes like saving a file
+/// or checking out a branch only causes a single search.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// `IndexWatcher` watches the searched directories for created, modified
+/// and deleted files.
+pub struct IndexWatcher {
+    // the watcher stops watching when it is dropped
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<Event>>,
+    /// The canonical watched directories.
+    roots: Vec<PathBuf>,
+    /// The options of the search, whose ignore files and filters decide
+    /// which changes are relevant.
+    options: SearchOptions,
+    /// Changes beneath this path are ignored, so that writing the cache
+    /// doesn't cause another search.
+    cache: Option<PathBuf>,
+}
+
+impl IndexWatcher {
+    /// `new()` starts watching the given directories recursively.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if one of the directories can't be watched.
+    pub fn new(directories: &[impl AsRef<str>], options: &SearchOptions) -> Result<Self, Error> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        let mut roots = Vec::new();
+        for directory in directories {
+            let directory = directory.as_ref();
+            let directory =
+                fs::canonicalize(directory).map_err(Error::file(Path::new(directory)))?;
+            watcher.watch(&directory, RecursiveMode::Recursive)?;
+            roots.push(directory);
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            roots,
+            options: options.clone(),
+            cache: options
+                .cache
+                .as_ref()
+                .map(|cache| fs::canonicalize(cache).unwrap_or_else(|_| cache.clone())),
+        })
+    }
+
+    /// `relevant_paths()` returns the paths of an event that could change the
+    /// result of a search, leaving out the cache and everything the ignore
+    /// files and filters of the search skip. If the watcher missed events,
+    /// all watched directories have to be searched again.
+    fn relevant_paths(&self, event: Event) -> Vec<PathBuf> {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return Vec::new();
+        }
+        if event.need_rescan() {
+            return self.roots.clone();
+        }
+
+        let paths = event
+            .paths
+            .into_iter()
+            .filter(|path| {
+                self.cache
+                    .as_ref()
+                    .map_or(true, |cache| !path.starts_with(cache))
+            })
+            .collect();
+        filter_ignored(&self.roots, paths, &self.options)
+    }
+
+    /// `wait()` blocks until files beneath the watched directories changed
+    /// and returns the changed paths. Changes that happen shortly after
+    /// each other are returned together. Changes of files that a search
+    /// skips are left out.
+    ///
+    /// # Errors
+    ///
+    /// This function errors if the watcher reports an error or stopped.
+    pub fn wait(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = Vec::new();
+
+        loop {
+            let event = if paths.is_empty() {
+                self.events
+                    .recv()
+                    .map_err(|e| Error::Watch(notify::Error::generic(&e.to_string())))?
+            } else {
+                match self.events.recv_timeout(DEBOUNCE) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(e) => return Err(Error::Watch(notify::Error::generic(&e.to_string()))),
+                }
+            }?;
+
+            paths.extend(self.relevant_paths(event));
+        }
+
+        paths.sort();
+        paths.dedup();
+
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexWatcher;
+    use crate::search::SearchOptions;
+
+    #[test]
+    fn test_index_watcher() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_path_buf();
+        let cache_dir = root.join("cache");
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".ignore"), "target/\n").unwrap();
+
+        let options = SearchOptions {
+            cache: Some(cache_dir.clone()),
+            ..Default::default()
+        };
+        let watcher = IndexWatcher::new(&[root.to_str().unwrap()], &options).unwrap();
+
+        let writer = {
+            let root = root.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                std::fs::write(cache_dir.join("cache.json"), "{}").unwrap();
+                std::fs::write(root.join("target/b.md"), "tags: [#b]\n").unwrap();
+                std::fs::write(root.join(".git/index"), "").unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                std::fs::write(root.join("a.md"), "tags: [#a]\n").unwrap();
+            })
+        };
+
+        let paths = watcher.wait().unwrap();
+        writer.join().unwrap();
+
+        let expected_path = std::fs::canonicalize(&root).unwrap().join("a.md");
+        assert_eq!(vec![expected_path], paths);
+    }
+}